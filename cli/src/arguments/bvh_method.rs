@@ -0,0 +1,14 @@
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+pub enum BvhMethod {
+    Sah,
+    Linear,
+}
+
+impl From<BvhMethod> for cpu_ray_tracer::bvh::builder::BvhMethod {
+    fn from(value: BvhMethod) -> Self {
+        match value {
+            BvhMethod::Sah => Self::Sah,
+            BvhMethod::Linear => Self::Linear,
+        }
+    }
+}
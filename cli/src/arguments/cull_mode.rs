@@ -0,0 +1,16 @@
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+pub enum CullMode {
+    None,
+    Back,
+    Front,
+}
+
+impl From<CullMode> for common::scene::CullMode {
+    fn from(value: CullMode) -> Self {
+        match value {
+            CullMode::None => Self::None,
+            CullMode::Back => Self::Back,
+            CullMode::Front => Self::Front,
+        }
+    }
+}
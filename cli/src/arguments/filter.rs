@@ -0,0 +1,20 @@
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+pub enum Filter {
+    Box,
+    Tent,
+    Gaussian,
+    Mitchell,
+    Lanczos,
+}
+
+impl From<Filter> for cpu_ray_tracer::filter::Filter {
+    fn from(value: Filter) -> Self {
+        match value {
+            Filter::Box => Self::Box,
+            Filter::Tent => Self::Tent,
+            Filter::Gaussian => Self::Gaussian,
+            Filter::Mitchell => Self::Mitchell,
+            Filter::Lanczos => Self::Lanczos,
+        }
+    }
+}
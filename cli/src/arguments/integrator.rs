@@ -0,0 +1,22 @@
+use cpu_ray_tracer::integrator::Integrator as RayTracerIntegrator;
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+pub enum Integrator {
+    Direct,
+    Path,
+    AmbientOcclusion,
+}
+
+impl Integrator {
+    // Ambient occlusion takes its settings from separate arguments
+    pub fn build(self, ao_rays: u32, ao_distance: f32) -> RayTracerIntegrator {
+        match self {
+            Integrator::Direct => RayTracerIntegrator::Direct,
+            Integrator::Path => RayTracerIntegrator::Path,
+            Integrator::AmbientOcclusion => RayTracerIntegrator::AmbientOcclusion {
+                rays: ao_rays,
+                max_distance: ao_distance,
+            },
+        }
+    }
+}
@@ -1,9 +1,23 @@
-use std::{path::PathBuf, str::FromStr};
+use std::{
+    num::{NonZeroU32, NonZeroUsize},
+    path::PathBuf,
+    str::FromStr,
+};
 
 use clap::Parser;
-
+use color_eyre::eyre::{Result, bail};
+use cpu_ray_tracer::bvh::builder::BvhSettings;
+
+pub mod bvh_method;
+pub mod cull_mode;
+pub mod filter;
+pub mod integrator;
+pub mod msaa;
 pub mod output;
 pub mod renderer;
+pub mod sampler;
+pub mod winding;
+pub mod wireframe;
 
 #[derive(Debug, Clone, Copy)]
 pub struct Vec3(pub glam::Vec3);
@@ -63,8 +77,131 @@ pub struct Args {
     #[arg(short, long)]
     pub output: Option<PathBuf>,
 
+    // Also writes out the depth buffer, in the same format as the output
+    #[arg(long)]
+    pub depth_output: Option<PathBuf>,
+
     #[arg(long)]
     pub resolution: Option<Resolution>,
 
+    // Number of worker threads for both renderers, defaults to the number of available cores
+    #[arg(long)]
+    pub threads: Option<NonZeroUsize>,
+
+    #[arg(long, value_enum, default_value = "none")]
+    pub cull_mode: cull_mode::CullMode,
+
+    // Overrides the winding order that the model loader picked for the meshes
+    #[arg(long, value_enum)]
+    pub winding: Option<winding::Winding>,
+
+    // Samples per pixel for the rasterizer's anti-aliasing
+    #[arg(long, value_enum, default_value = "1")]
+    pub msaa: msaa::Msaa,
+
+    // Draws the edges of the triangles, either on their own or on top of the shaded image
+    #[arg(long, value_enum)]
+    pub wireframe: Option<wireframe::WireframeMode>,
+
+    // Edge color as r,g,b from 0 to 1, defaults to black
+    #[arg(long)]
+    pub wireframe_color: Option<Vec3>,
+
+    // Edge width in pixels
+    #[arg(long, default_value_t = 1.0)]
+    pub wireframe_thickness: f32,
+
+    // How the ray tracer lights the scene
+    #[arg(long, value_enum, default_value = "direct")]
+    pub integrator: integrator::Integrator,
+
+    // Hemisphere rays per hit for ambient occlusion
+    #[arg(long, default_value_t = 16)]
+    pub ao_rays: u32,
+
+    // How far away geometry still occludes for ambient occlusion, unlimited by default
+    #[arg(long)]
+    pub ao_distance: Option<f32>,
+
+    // Maximum number of surfaces a ray tracer path can hit, also limits reflection and refraction
+    #[arg(long, default_value_t = 8)]
+    pub max_depth: u32,
+
+    // Samples per pixel for the ray tracer
+    #[arg(long, default_value = "1")]
+    pub spp: NonZeroU32,
+
+    // How the ray tracer weights the samples of a pixel
+    #[arg(long, value_enum, default_value = "box")]
+    pub filter: filter::Filter,
+
+    // Where the ray tracer's sample positions and random decisions come from
+    #[arg(long, value_enum, default_value = "stratified")]
+    pub sampler: sampler::Sampler,
+
+    // Keep adding samples to the pixels that are still noisy, instead of a fixed --spp
+    #[arg(long, default_value_t = false)]
+    pub adaptive: bool,
+
+    // Relative noise level at which adaptive sampling stops refining a pixel
+    #[arg(long, default_value_t = 0.01)]
+    pub max_error: f32,
+
+    // Upper limit on the samples per pixel for adaptive sampling
+    #[arg(long, default_value = "4096")]
+    pub max_spp: NonZeroU32,
+
+    // Stops adaptive sampling after this many seconds, implies --adaptive
+    #[arg(long)]
+    pub time_budget: Option<f32>,
+
+    // How the ray tracer builds its BVH: sah traces faster, linear builds faster
+    #[arg(long, value_enum)]
+    pub bvh_method: Option<bvh_method::BvhMethod>,
+
+    // Ray tracer BVH nodes with more triangles than this are always split
+    #[arg(long)]
+    pub bvh_max_leaf_size: Option<NonZeroU32>,
+
+    // Cost of traversing a BVH node, relative to --bvh-intersection-cost
+    #[arg(long)]
+    pub bvh_traversal_cost: Option<f32>,
+
+    // Cost of intersecting a triangle, relative to --bvh-traversal-cost
+    #[arg(long)]
+    pub bvh_intersection_cost: Option<f32>,
+
+    // Number of equally sized bins along each axis that the BVH builder tries splits between
+    #[arg(long)]
+    pub bvh_bins: Option<NonZeroU32>,
+
     pub scene: PathBuf,
 }
+
+impl Args {
+    // The ray tracer's BVH settings, with the defaults for anything that wasn't passed
+    pub fn bvh_settings(&self) -> Result<BvhSettings> {
+        if self.bvh_bins.is_some_and(|bins| bins.get() < 2) {
+            bail!("--bvh-bins needs at least 2 bins to split between");
+        }
+        for (name, cost) in [
+            ("--bvh-traversal-cost", self.bvh_traversal_cost),
+            ("--bvh-intersection-cost", self.bvh_intersection_cost),
+        ] {
+            if cost.is_some_and(|cost| !cost.is_finite() || cost < 0.0) {
+                bail!("{name} must be a finite number that isn't negative");
+            }
+        }
+
+        let defaults = BvhSettings::default();
+        Ok(BvhSettings {
+            method: self.bvh_method.map_or(defaults.method, Into::into),
+            max_leaf_size: self.bvh_max_leaf_size.unwrap_or(defaults.max_leaf_size),
+            traversal_cost: self.bvh_traversal_cost.unwrap_or(defaults.traversal_cost),
+            intersection_cost: self
+                .bvh_intersection_cost
+                .unwrap_or(defaults.intersection_cost),
+            bins: self.bvh_bins.unwrap_or(defaults.bins),
+        })
+    }
+}
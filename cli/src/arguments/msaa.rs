@@ -0,0 +1,22 @@
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+pub enum Msaa {
+    #[value(name = "1")]
+    Off,
+    #[value(name = "2")]
+    X2,
+    #[value(name = "4")]
+    X4,
+    #[value(name = "8")]
+    X8,
+}
+
+impl From<Msaa> for cpu_rasterizer::msaa::Msaa {
+    fn from(value: Msaa) -> Self {
+        match value {
+            Msaa::Off => Self::Off,
+            Msaa::X2 => Self::X2,
+            Msaa::X4 => Self::X4,
+            Msaa::X8 => Self::X8,
+        }
+    }
+}
@@ -0,0 +1,18 @@
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+pub enum Sampler {
+    Independent,
+    Stratified,
+    Halton,
+    Sobol,
+}
+
+impl From<Sampler> for cpu_ray_tracer::sampler::SamplerKind {
+    fn from(value: Sampler) -> Self {
+        match value {
+            Sampler::Independent => Self::Independent,
+            Sampler::Stratified => Self::Stratified,
+            Sampler::Halton => Self::Halton,
+            Sampler::Sobol => Self::Sobol,
+        }
+    }
+}
@@ -0,0 +1,14 @@
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+pub enum Winding {
+    CounterClockwise,
+    Clockwise,
+}
+
+impl From<Winding> for common::model::triangle::Winding {
+    fn from(value: Winding) -> Self {
+        match value {
+            Winding::CounterClockwise => Self::CounterClockwise,
+            Winding::Clockwise => Self::Clockwise,
+        }
+    }
+}
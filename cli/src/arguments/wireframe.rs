@@ -0,0 +1,14 @@
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+pub enum WireframeMode {
+    Only,
+    Overlay,
+}
+
+impl From<WireframeMode> for common::wireframe::WireframeMode {
+    fn from(value: WireframeMode) -> Self {
+        match value {
+            WireframeMode::Only => Self::Only,
+            WireframeMode::Overlay => Self::Overlay,
+        }
+    }
+}
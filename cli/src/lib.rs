@@ -1,13 +1,14 @@
-use color_eyre::eyre::Result;
+use color_eyre::eyre::{Result, bail};
 use core::f32;
 use cpu_rasterizer::CpuRasterizer;
-use cpu_ray_tracer::CpuRayTracer;
+use cpu_ray_tracer::{CpuRayTracer, adaptive::AdaptiveSampling};
 use glam::Vec3;
 use serde::Deserialize;
 use std::{
     fs::{File, OpenOptions, read_dir},
     io::{BufWriter, Write, stdout},
     path::PathBuf,
+    time::Duration,
 };
 use tap::Tap;
 
@@ -17,10 +18,13 @@ use common::{
     light::Light,
     model::{
         format::obj::load_obj,
-        triangle::{Mesh, Triangle, Vertex},
+        material::Material,
+        triangle::{Mesh, Triangle, Vertex, Winding},
     },
-    scene::{Scene, SceneBuilder},
-    surface::Surface,
+    parallel,
+    scene::SceneBuilder,
+    surface::{DepthBuffer, Surface},
+    wireframe::Wireframe,
 };
 
 use crate::arguments::{Resolution, output::OutputFormat};
@@ -32,7 +36,7 @@ pub mod arguments;
 // const SCENE: (&str, glam::Vec3) = ("./assets/scenes/cube", glam::Vec3::new(2.0, 1.0, 1.0));
 // const SCENE: (&str, glam::Vec3) = ("./assets/scenes/teapot", glam::Vec3::new(50.0, 90.0, 120.0));
 
-fn debug_scene(surface: &Surface) -> Scene {
+fn debug_scene(surface: &Surface) -> SceneBuilder {
     // old single triangle replaced with a hexagon made of 6 triangles
     let hex_radius = 1.0;
     let vertices: Vec<glam::Vec3> = (0..6)
@@ -74,6 +78,9 @@ fn debug_scene(surface: &Surface) -> Scene {
             glam::Vec3::new(-hex_radius, -hex_radius, 0.0),
             glam::Vec3::new(hex_radius, hex_radius, 0.0),
         ),
+        // center -> v2 -> v1 goes clockwise when looking at it from +Z, where the normals point
+        winding: Winding::Clockwise,
+        material: Material::default(),
     };
 
     let camera = Camera::look_at(
@@ -83,10 +90,7 @@ fn debug_scene(surface: &Surface) -> Scene {
         60.0,
         surface.width() as f32 / surface.height() as f32,
     );
-    SceneBuilder::new()
-        .with_camera(camera)
-        .add_mesh(mesh)
-        .build()
+    SceneBuilder::new().with_camera(camera).add_mesh(mesh)
 }
 
 #[derive(Deserialize)]
@@ -95,13 +99,16 @@ struct CameraSettings {
     look_at: Option<glam::Vec3>,
     horizontal_fov: Option<f32>,
     up: Option<glam::Vec3>,
+    near: Option<f32>,
+    far: Option<f32>,
 }
 
 fn load_scene(
     scene_path: PathBuf,
     surface: &Surface,
     camera_origin: Option<glam::Vec3>,
-) -> Result<Scene> {
+    winding: Option<Winding>,
+) -> Result<SceneBuilder> {
     // List all the files in the directory
     let dir = read_dir(&scene_path)?;
 
@@ -117,6 +124,12 @@ fn load_scene(
         }
     }
 
+    if let Some(winding) = winding {
+        for mesh in &mut meshes {
+            mesh.winding = winding;
+        }
+    }
+
     let bounding_box = meshes
         .iter()
         .map(|m| m.bounding_box)
@@ -142,6 +155,12 @@ fn load_scene(
         camera_settings.horizontal_fov.unwrap_or(80.0),
         surface.width() as f32 / surface.height() as f32,
     );
+    let near = camera_settings.near.unwrap_or(camera.near());
+    let far = camera_settings.far.unwrap_or(camera.far());
+    if !(0.0 < near && near < far) {
+        bail!("camera.json needs 0 < near < far, but near is {near} and far is {far}");
+    }
+    let camera = camera.with_clip_planes(near, far);
 
     Ok(SceneBuilder::new()
         .with_camera(camera)
@@ -149,8 +168,7 @@ fn load_scene(
         .add_light(Light::Sun {
             direction: Vec3::ONE.normalize(),
             intensity: 0.8,
-        })
-        .build())
+        }))
 }
 
 pub fn run(args: arguments::Args) -> Result<()> {
@@ -160,6 +178,8 @@ pub fn run(args: arguments::Args) -> Result<()> {
         height: 1080,
     });
     let mut surface = Surface::new(resolution.width, resolution.height);
+    // Checked before the scene gets loaded, which can take a while
+    let bvh_settings = args.bvh_settings()?;
 
     // Render
     let camera_option = args.camera_origin.map(|c| c.0);
@@ -167,22 +187,81 @@ pub fn run(args: arguments::Args) -> Result<()> {
     let scene = if args.debug {
         debug_scene(&surface)
     } else {
-        load_scene(args.scene, &surface, camera_option)?
-    };
+        load_scene(
+            args.scene,
+            &surface,
+            camera_option,
+            args.winding.map(Into::into),
+        )?
+    }
+    .with_cull_mode(args.cull_mode.into())
+    .build();
+
+    let wireframe = args.wireframe.map(|mode| Wireframe {
+        mode: mode.into(),
+        color: args
+            .wireframe_color
+            .map_or(Wireframe::default().color, |c| c.0),
+        thickness: args.wireframe_thickness,
+    });
 
     match args.renderer {
         arguments::renderer::Renderer::CpuRasterizer => {
-            let renderer = CpuRasterizer::new(scene);
-            renderer.render(&mut surface);
+            let mut renderer = CpuRasterizer::new(scene).with_msaa(args.msaa.into());
+            if let Some(threads) = args.threads {
+                renderer = renderer.with_threads(threads);
+            }
+            if let Some(wireframe) = wireframe {
+                renderer = renderer.with_wireframe(wireframe);
+            }
+            if let Some(depth_output) = args.depth_output {
+                let mut depth_buffer =
+                    DepthBuffer::filled(resolution.width, resolution.height, 1.0);
+                renderer.render_with_depth(&mut surface, &mut depth_buffer);
+                write_image(
+                    &depth_buffer.to_grayscale(),
+                    &args.format,
+                    Some(depth_output),
+                )?;
+            } else {
+                renderer.render(&mut surface);
+            }
         }
         arguments::renderer::Renderer::CpuRayTracer => {
-            let renderer = CpuRayTracer::new(scene);
+            if args.depth_output.is_some() {
+                bail!("--depth-output is only supported by the rasterizer");
+            }
+            let threads = args.threads.unwrap_or_else(parallel::default_threads);
+            let mut renderer = CpuRayTracer::new_with_bvh_settings(scene, bvh_settings, threads)
+                .with_integrator(
+                    args.integrator
+                        .build(args.ao_rays, args.ao_distance.unwrap_or(f32::INFINITY)),
+                )
+                .with_max_depth(args.max_depth)
+                .with_samples_per_pixel(args.spp)
+                .with_filter(args.filter.into())
+                .with_sampler(args.sampler.into());
+            if args.adaptive || args.time_budget.is_some() {
+                renderer = renderer.with_adaptive_sampling(AdaptiveSampling {
+                    max_error: args.max_error,
+                    max_samples: args.max_spp,
+                    time_budget: args.time_budget.map(Duration::from_secs_f32),
+                    ..Default::default()
+                });
+            }
+            if let Some(wireframe) = wireframe {
+                renderer = renderer.with_wireframe(wireframe);
+            }
             renderer.render(&mut surface);
         }
     }
 
-    // Write the file
-    let mut writer: Box<dyn Write> = if let Some(output) = args.output {
+    write_image(&surface, &args.format, args.output)
+}
+
+// Writes the surface to the output file, or to stdout if there is none
+fn write_image(surface: &Surface, format: &OutputFormat, output: Option<PathBuf>) -> Result<()> {
+    let mut writer: Box<dyn Write> = if let Some(output) = output {
         Box::new(BufWriter::new(
             OpenOptions::new()
                 .create(true)
@@ -193,18 +272,18 @@ pub fn run(args: arguments::Args) -> Result<()> {
     } else {
         Box::new(BufWriter::new(stdout()))
     };
-    match args.format {
+    match format {
         OutputFormat::JpegXl => {
             let jxl = JpegXl { lossless: true };
 
-            jxl.save(&surface, &mut writer)?;
+            jxl.save(surface, &mut writer)?;
         }
 
         OutputFormat::Ppm => {
             let ppm = ppm::Ppm {
                 format: ppm::PpmFormat::Binary,
             };
-            ppm.save(&surface, &mut writer)?;
+            ppm.save(surface, &mut writer)?;
         }
 
         OutputFormat::None => {}
@@ -1,3 +1,6 @@
+const DEFAULT_NEAR: f32 = 0.1;
+const DEFAULT_FAR: f32 = 1000.0;
+
 #[derive(Debug, Clone, Copy)]
 pub struct Camera {
     origin: glam::Vec3,
@@ -5,6 +8,10 @@ pub struct Camera {
 
     up: glam::Vec3,
     right: glam::Vec3,
+
+    // Distances to the near and far clipping planes
+    near: f32,
+    far: f32,
 }
 
 impl Default for Camera {
@@ -47,9 +54,18 @@ impl Camera {
             view,
             up: up * height,
             right: right * width,
+            near: DEFAULT_NEAR,
+            far: DEFAULT_FAR,
         }
     }
 
+    pub fn with_clip_planes(mut self, near: f32, far: f32) -> Self {
+        assert!(0.0 < near && near < far, "Expected 0 < near < far");
+        self.near = near;
+        self.far = far;
+        self
+    }
+
     pub fn look_at(
         origin: glam::Vec3,
         target: glam::Vec3,
@@ -70,9 +86,47 @@ impl Camera {
         self.origin
     }
 
-    // Maps world space into NDC
-    pub fn projection_matrix(&self) -> glam::Vec3 {
-        todo!()
+    #[inline]
+    pub fn near(&self) -> f32 {
+        self.near
+    }
+
+    #[inline]
+    pub fn far(&self) -> f32 {
+        self.far
+    }
+
+    // Maps world space into view space (right-handed, looking down -Z)
+    pub fn view_matrix(&self) -> glam::Mat4 {
+        glam::Mat4::look_to_rh(self.origin, self.view, self.up.normalize())
+    }
+
+    // Maps view space into clip space. After the perspective divide, x and y match the NDC
+    // used by ndc_to_viewing_direction, and z goes from 0 (near plane) to 1 (far plane)
+    pub fn projection_matrix(&self) -> glam::Mat4 {
+        let height = self.up.length();
+        let width = self.right.length();
+        glam::Mat4::perspective_rh(2.0 * height.atan(), width / height, self.near, self.far)
+    }
+
+    // Maps world space into clip space
+    pub fn view_projection_matrix(&self) -> glam::Mat4 {
+        self.projection_matrix() * self.view_matrix()
+    }
+
+    // Maps clip space back into world space
+    pub fn inverse_view_projection_matrix(&self) -> glam::Mat4 {
+        self.view_projection_matrix().inverse()
+    }
+
+    // Maps a world space point into NDC
+    pub fn world_to_ndc(&self, point: glam::Vec3) -> glam::Vec3 {
+        self.view_projection_matrix().project_point3(point)
+    }
+
+    // Maps a point in NDC back into world space
+    pub fn ndc_to_world(&self, ndc: glam::Vec3) -> glam::Vec3 {
+        self.inverse_view_projection_matrix().project_point3(ndc)
     }
 }
 
@@ -167,4 +221,72 @@ mod tests {
             )
         }
     }
+
+    #[test]
+    fn test_projection_round_trip() {
+        let camera =
+            Camera::new(Vec3::ZERO, Vec3::X, Vec3::Z, 90.0, 2.0).with_clip_planes(0.5, 100.0);
+
+        for point in [
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(10.0, 3.0, -2.0),
+            Vec3::new(50.0, -20.0, 10.0),
+            Vec3::new(99.0, 40.0, 20.0),
+        ] {
+            let ndc = camera.world_to_ndc(point);
+            let unprojected = camera.ndc_to_world(ndc);
+            assert!(
+                unprojected.abs_diff_eq(point, 1e-4 * point.length()),
+                "{point} -> {ndc} -> {unprojected}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_projection_matches_ndc() {
+        // Same camera as test_ndc
+        let camera = Camera::new(Vec3::ZERO, Vec3::X, Vec3::Z, 90.0, 2.0);
+
+        for ndc in [
+            Vec2::new(0.0, 0.0),
+            Vec2::new(-1.0, 0.0),
+            Vec2::new(1.0, 0.0),
+            Vec2::new(0.0, 1.0),
+            Vec2::new(0.0, -1.0),
+            Vec2::new(-1.0, 1.0),
+            Vec2::new(1.0, -1.0),
+        ] {
+            let viewing_direction = camera.ndc_to_viewing_direction(ndc);
+
+            // Every point along the viewing direction projects onto the same NDC
+            for distance in [1.0, 10.0, 100.0] {
+                let projected = camera.world_to_ndc(camera.origin() + viewing_direction * distance);
+                assert!(
+                    projected.truncate().abs_diff_eq(ndc, 1e-5),
+                    "{ndc}: projected to {projected}"
+                );
+            }
+
+            // And unprojecting that NDC gives back the viewing direction, at any depth
+            for depth in [0.0, 0.5, 1.0] {
+                let unprojected = camera.ndc_to_world(ndc.extend(depth));
+                let direction = (unprojected - camera.origin()).normalize();
+                assert!(
+                    direction.abs_diff_eq(viewing_direction, 1e-5),
+                    "{ndc}: unprojected to {direction}, expected {viewing_direction}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_projection_depth() {
+        let camera =
+            Camera::new(Vec3::ZERO, Vec3::X, Vec3::Z, 90.0, 2.0).with_clip_planes(0.5, 100.0);
+
+        let near = camera.world_to_ndc(Vec3::new(0.5, 0.0, 0.0));
+        let far = camera.world_to_ndc(Vec3::new(100.0, 0.0, 0.0));
+        assert!((near.z - 0.0).abs() < 1e-5, "near plane: {near}");
+        assert!((far.z - 1.0).abs() < 1e-5, "far plane: {far}");
+    }
 }
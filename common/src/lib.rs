@@ -2,6 +2,8 @@ pub mod camera;
 pub mod image;
 pub mod light;
 pub mod model;
+pub mod parallel;
 pub mod scene;
 pub mod surface;
 mod util;
+pub mod wireframe;
@@ -1,2 +1,3 @@
+pub mod mtl;
 pub mod obj;
 pub mod stl;
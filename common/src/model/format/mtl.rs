@@ -0,0 +1,158 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+};
+
+use glam::Vec3;
+
+use crate::model::material::Material;
+
+// Parses a Vec3 from 3 values, or a gray one from a single value
+fn parse_color(values: &[f32]) -> Option<Vec3> {
+    match values {
+        [gray] => Some(Vec3::splat(*gray)),
+        [r, g, b, ..] => Some(Vec3::new(*r, *g, *b)),
+        _ => None,
+    }
+}
+
+// Loads the materials in an MTL file, by name.
+// Only the properties that the renderers use are parsed, the rest is ignored. A file that can't be
+// opened only gets a warning, so a missing library doesn't stop the model from loading.
+pub fn load_mtl<P: AsRef<Path>>(path: P) -> HashMap<String, Material> {
+    let path = path.as_ref();
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(error) => {
+            eprintln!("WARNING: can't open material library {path:?}: {error}");
+            return HashMap::new();
+        }
+    };
+    let reader = BufReader::new(file);
+
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, Material)> = None;
+
+    for l in reader.lines().map_while(Result::ok) {
+        let l = l.trim();
+        if l.is_empty() || l.starts_with("#") {
+            // Empty line or comment
+            continue;
+        }
+
+        if let Some(name) = l.strip_prefix("newmtl ") {
+            // If there's already a material, save it
+            if let Some((name, material)) = current.take() {
+                materials.insert(name, material);
+            }
+            current = Some((name.trim().to_string(), Material::default()));
+            continue;
+        }
+
+        let Some((_, material)) = &mut current else {
+            eprintln!("WARNING: MTL statement outside of a material: {l:?}");
+            continue;
+        };
+
+        // Some files put several statements on one line (like "Tr 0  illum 2"), so read every
+        // keyword along with the numbers that follow it
+        let mut tokens = l.split_whitespace().peekable();
+        while let Some(keyword) = tokens.next() {
+            let mut values = Vec::new();
+            while let Some(value) = tokens.peek().and_then(|t| t.parse::<f32>().ok()) {
+                values.push(value);
+                tokens.next();
+            }
+
+            match keyword {
+                "Kd" => material.diffuse = parse_color(&values).unwrap_or(material.diffuse),
+                "Ks" => material.specular = parse_color(&values).unwrap_or(material.specular),
+                "Ni" => {
+                    material.index_of_refraction = values
+                        .first()
+                        .copied()
+                        .unwrap_or(material.index_of_refraction)
+                }
+                "Tr" => {
+                    material.transparency = values.first().copied().unwrap_or(material.transparency)
+                }
+                // Dissolve is the opposite of transparency
+                "d" => {
+                    if let Some(dissolve) = values.first() {
+                        material.transparency = 1.0 - dissolve;
+                    }
+                }
+                // Texture maps and the like have a file name as an argument, skip that too
+                _ => {
+                    if values.is_empty() {
+                        tokens.next();
+                    }
+                }
+            }
+        }
+    }
+
+    // Also put the final material in the map
+    if let Some((name, material)) = current.take() {
+        materials.insert(name, material);
+    }
+
+    materials
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn test_load_mtl() {
+        let path = std::env::temp_dir().join(format!("test_load_mtl_{}.mtl", std::process::id()));
+        fs::write(
+            &path,
+            "# Two materials\n\
+             newmtl glass\n\
+             Kd 0.1 0.2 0.3\n\
+             Ks 0.9\n\
+             Ni 1.5\n\
+             Tr 0.8  illum 7\n\
+             map_Kd glass.png\n\
+             \n\
+             newmtl mirror\n\
+             Ks 1 0.5 0.25\n\
+             d 0.75\n",
+        )
+        .unwrap();
+        let materials = load_mtl(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(materials.len(), 2);
+        assert_eq!(
+            materials["glass"],
+            Material {
+                diffuse: Vec3::new(0.1, 0.2, 0.3),
+                specular: Vec3::splat(0.9),
+                index_of_refraction: 1.5,
+                transparency: 0.8,
+            }
+        );
+        assert_eq!(
+            materials["mirror"],
+            Material {
+                specular: Vec3::new(1.0, 0.5, 0.25),
+                transparency: 0.25,
+                ..Material::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_missing_mtl_has_no_materials() {
+        let path =
+            std::env::temp_dir().join(format!("test_missing_mtl_{}.mtl", std::process::id()));
+        assert!(load_mtl(path).is_empty());
+    }
+}
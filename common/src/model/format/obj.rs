@@ -1,14 +1,18 @@
 use std::{
+    collections::HashMap,
     fs::File,
     io::{BufRead, BufReader},
-    num::NonZero,
     path::Path,
 };
 
 use glam::{Vec2, Vec3};
-use tap::{Pipe, Tap};
+use tap::Pipe;
 
-use crate::model::triangle::{Mesh, Triangle, Vertex};
+use crate::model::{
+    format::mtl::load_mtl,
+    material::Material,
+    triangle::{Mesh, Triangle, Vertex, Winding},
+};
 
 struct ObjVertex {
     position: Vec3,
@@ -28,6 +32,12 @@ struct ObjFaceVertex {
     normal_index: Option<usize>,
 }
 
+#[derive(Debug, Clone, Default)]
+struct ObjGroup {
+    faces: Vec<ObjFace>,
+    material: Material,
+}
+
 #[derive(Debug, Clone, Copy)]
 struct ObjFace {
     vertices: [ObjFaceVertex; 3],
@@ -141,6 +151,11 @@ fn convert_face_to_triangle(
 }
 
 pub fn load_obj<P: AsRef<Path>>(path: P) -> Vec<Mesh> {
+    let directory = path
+        .as_ref()
+        .parent()
+        .unwrap_or(Path::new("."))
+        .to_path_buf();
     let file = File::open(path).unwrap();
     let reader = BufReader::new(file);
 
@@ -148,9 +163,11 @@ pub fn load_obj<P: AsRef<Path>>(path: P) -> Vec<Mesh> {
     let mut vertex_uvs = vec![];
     let mut vertex_normals = vec![];
 
-    let mut groups: Vec<Vec<ObjFace>> = Vec::new();
+    let mut materials: HashMap<String, Material> = HashMap::new();
+
+    let mut groups: Vec<ObjGroup> = Vec::new();
 
-    let mut current_group: Option<Vec<ObjFace>> = None;
+    let mut current_group: Option<ObjGroup> = None;
 
     // Process the file line by line
     for l in reader.lines().map_while(Result::ok) {
@@ -190,13 +207,43 @@ pub fn load_obj<P: AsRef<Path>>(path: P) -> Vec<Mesh> {
             continue;
         }
 
-        if let Some(name) = l.strip_prefix("g ").map(str::trim) {
+        if l.strip_prefix("g ").is_some() {
             // If there's already a group, save it
             if let Some(mesh) = current_group.take() {
                 groups.push(mesh);
             }
 
-            current_group = Some(Vec::new());
+            // The new group keeps using the same material, until it says otherwise
+            let material = groups.last().map(|g| g.material).unwrap_or_default();
+            current_group = Some(ObjGroup {
+                faces: Vec::new(),
+                material,
+            });
+            continue;
+        }
+
+        // Material library, relative to the OBJ file
+        if let Some(file_names) = l.strip_prefix("mtllib ") {
+            for file_name in file_names.split_whitespace() {
+                materials.extend(load_mtl(directory.join(file_name)));
+            }
+            continue;
+        }
+
+        if let Some(name) = l.strip_prefix("usemtl ") {
+            let Some(material) = materials.get(name.trim()).copied() else {
+                println!("WARNING: unknown material: {name:?}");
+                continue;
+            };
+
+            // Meshes have a single material, so faces after a material change get their own mesh
+            let mut group = current_group.take().unwrap_or_default();
+            if !group.faces.is_empty() {
+                groups.push(group);
+                group = ObjGroup::default();
+            }
+            group.material = material;
+            current_group = Some(group);
             continue;
         }
 
@@ -215,7 +262,7 @@ pub fn load_obj<P: AsRef<Path>>(path: P) -> Vec<Mesh> {
                 }
             }
 
-            group.extend(faces);
+            group.faces.extend(faces);
             current_group = Some(group);
             // break;
             continue;
@@ -241,8 +288,10 @@ pub fn load_obj<P: AsRef<Path>>(path: P) -> Vec<Mesh> {
     // turn Vec<ObjFace> into Meshes
     groups
         .iter()
+        .filter(|g| !g.faces.is_empty())
         .map(|g| {
             let triangles = g
+                .faces
                 .iter()
                 .map(|f| {
                     convert_face_to_triangle(
@@ -253,7 +302,10 @@ pub fn load_obj<P: AsRef<Path>>(path: P) -> Vec<Mesh> {
                     )
                 })
                 .collect();
+            // The OBJ format specifies that faces are counter-clockwise
             Mesh::new(triangles)
+                .with_winding(Winding::CounterClockwise)
+                .with_material(g.material)
         })
         .collect()
 }
@@ -1,13 +1,15 @@
 use std::collections::HashMap;
 
 use crate::{
-    model::triangle::{Triangle, Vertex},
+    model::triangle::{Triangle, Vertex, Winding},
     util::BufGlamExt,
 };
 use bytes::{Buf, Bytes};
 use glam::Vec3;
 
-use crate::model::triangle::Mesh;
+use crate::model::{material::Material, triangle::Mesh};
+
+type GridCoords = (usize, usize, usize);
 
 pub fn load_stl(mut bytes: Bytes) -> Mesh {
     bytes.advance(80); // Skip the header
@@ -16,13 +18,8 @@ pub fn load_stl(mut bytes: Bytes) -> Mesh {
     let mut bounding_box_max = Vec3::MIN;
 
     // (position, normals)
-    let mut stl_vertices: HashMap<(usize, usize, usize), (glam::Vec3, Vec<glam::Vec3>)> =
-        HashMap::new();
-    let mut triangle_vertex_indices: Vec<(
-        (usize, usize, usize),
-        (usize, usize, usize),
-        (usize, usize, usize),
-    )> = vec![];
+    let mut stl_vertices: HashMap<GridCoords, (glam::Vec3, Vec<glam::Vec3>)> = HashMap::new();
+    let mut triangle_vertex_indices: Vec<(GridCoords, GridCoords, GridCoords)> = vec![];
 
     // Returns the index in the vertices array
     let mut update_vertices = |position: glam::Vec3, normal: glam::Vec3| {
@@ -41,6 +38,11 @@ pub fn load_stl(mut bytes: Bytes) -> Mesh {
         grid_coords
     };
 
+    // STL is supposed to be counter-clockwise, but not every exporter sticks to that.
+    // The facet normals point outwards, so we count which winding order agrees with them.
+    let mut num_counter_clockwise = 0;
+    let mut num_clockwise = 0;
+
     for _ in 0..num_triangles {
         let normal = bytes.get_vec3_le();
         let v1 = bytes.get_vec3_le();
@@ -48,6 +50,14 @@ pub fn load_stl(mut bytes: Bytes) -> Mesh {
         let v3 = bytes.get_vec3_le();
         bytes.advance(2); // attribute byte count
 
+        // Some exporters leave the facet normal at zero, those don't count
+        let facing = (v2 - v1).cross(v3 - v1).dot(normal);
+        if facing > 0.0 {
+            num_counter_clockwise += 1;
+        } else if facing < 0.0 {
+            num_clockwise += 1;
+        }
+
         let v1_index = update_vertices(v1, normal);
         let v2_index = update_vertices(v2, normal);
         let v3_index = update_vertices(v3, normal);
@@ -76,17 +86,70 @@ pub fn load_stl(mut bytes: Bytes) -> Mesh {
     let triangles = triangle_vertex_indices
         .into_iter()
         .map(|(i1, i2, i3)| Triangle {
-            v1: vertices.get(&i1).unwrap().clone(),
-            v2: vertices.get(&i2).unwrap().clone(),
-            v3: vertices.get(&i3).unwrap().clone(),
+            v1: *vertices.get(&i1).unwrap(),
+            v2: *vertices.get(&i2).unwrap(),
+            v3: *vertices.get(&i3).unwrap(),
         })
         .collect::<Vec<_>>();
 
     let center = (bounding_box_min + bounding_box_max) / 2.0;
 
+    let winding = if num_clockwise > num_counter_clockwise {
+        Winding::Clockwise
+    } else {
+        Winding::CounterClockwise
+    };
+
     Mesh {
         triangles,
         bounding_box: (bounding_box_min, bounding_box_max),
         center,
+        winding,
+        material: Material::default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::{BufMut, BytesMut};
+
+    use super::*;
+
+    // A binary STL of a tetrahedron, with its facets in the given winding order and their normals
+    // pointing outwards. One facet has no normal, like some exporters write.
+    fn tetrahedron(winding: Winding) -> Bytes {
+        let [p0, p1, p2, p3] = [Vec3::ZERO, Vec3::X, Vec3::Y, Vec3::Z];
+        let counter_clockwise = [[p0, p2, p1], [p0, p1, p3], [p0, p3, p2], [p1, p2, p3]];
+
+        let mut bytes = BytesMut::new();
+        bytes.put_bytes(0, 80);
+        bytes.put_u32_le(counter_clockwise.len() as u32);
+        for (i, [v1, v2, v3]) in counter_clockwise.into_iter().enumerate() {
+            let normal = if i == 0 {
+                Vec3::ZERO
+            } else {
+                (v2 - v1).cross(v3 - v1).normalize()
+            };
+            let vertices = match winding {
+                Winding::CounterClockwise => [v1, v2, v3],
+                Winding::Clockwise => [v1, v3, v2],
+            };
+            for v in [normal].into_iter().chain(vertices) {
+                bytes.put_f32_le(v.x);
+                bytes.put_f32_le(v.y);
+                bytes.put_f32_le(v.z);
+            }
+            bytes.put_u16_le(0);
+        }
+        bytes.freeze()
+    }
+
+    #[test]
+    fn test_winding_is_detected() {
+        for winding in [Winding::CounterClockwise, Winding::Clockwise] {
+            let mesh = load_stl(tetrahedron(winding));
+            assert_eq!(mesh.triangles.len(), 4);
+            assert_eq!(mesh.winding, winding);
+        }
     }
 }
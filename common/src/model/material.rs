@@ -0,0 +1,21 @@
+use glam::Vec3;
+
+// Surface properties of a mesh, as far as the renderers understand them
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Material {
+    pub diffuse: Vec3,            // Kd, multiplies the texture
+    pub specular: Vec3,           // Ks, how much of a mirror reflection opaque surfaces add
+    pub index_of_refraction: f32, // Ni
+    pub transparency: f32,        // Tr, from 0 (opaque) to 1 (clear glass)
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            diffuse: Vec3::ONE,
+            specular: Vec3::ZERO,
+            index_of_refraction: 1.0,
+            transparency: 0.0,
+        }
+    }
+}
@@ -1,2 +1,3 @@
 pub mod format;
+pub mod material;
 pub mod triangle;
@@ -1,3 +1,5 @@
+use crate::model::material::Material;
+
 #[derive(Debug, Clone, Copy)]
 pub struct Vertex {
     pub position: glam::Vec3,
@@ -23,11 +25,58 @@ pub struct Triangle {
     pub v3: Vertex, // 24 bytes
 }
 
+impl Triangle {
+    // The same triangle, with the opposite winding order
+    pub fn flipped(&self) -> Self {
+        Self {
+            v1: self.v1,
+            v2: self.v3,
+            v3: self.v2,
+        }
+    }
+
+    // The triangle moved by transform. Normals are transformed by the inverse transpose, which
+    // keeps them perpendicular to the surface under non-uniform scaling. Transforms that mirror
+    // the triangle also flip it, so it keeps its winding order relative to its normals.
+    pub fn transformed(&self, transform: &glam::Affine3A) -> Self {
+        if *transform == glam::Affine3A::IDENTITY {
+            return *self;
+        }
+
+        let normal_matrix = transform.matrix3.inverse().transpose();
+        let vertex = |v: &Vertex| Vertex {
+            position: transform.transform_point3(v.position),
+            normal: (normal_matrix * v.normal).normalize_or_zero(),
+            uv: v.uv,
+        };
+        let triangle = Self {
+            v1: vertex(&self.v1),
+            v2: vertex(&self.v2),
+            v3: vertex(&self.v3),
+        };
+        if transform.matrix3.determinant() < 0.0 {
+            triangle.flipped()
+        } else {
+            triangle
+        }
+    }
+}
+
+// The order in which the vertices of a front facing triangle appear, when looking at its front
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Winding {
+    #[default]
+    CounterClockwise,
+    Clockwise,
+}
+
 #[derive(Clone, Debug)]
 pub struct Mesh {
     pub triangles: Vec<Triangle>,
     pub bounding_box: (glam::Vec3, glam::Vec3),
     pub center: glam::Vec3,
+    pub winding: Winding,
+    pub material: Material,
 }
 
 impl Mesh {
@@ -52,6 +101,26 @@ impl Mesh {
             triangles,
             bounding_box: (bb_min, bb_max),
             center,
+            winding: Winding::default(),
+            material: Material::default(),
         }
     }
+
+    pub fn with_winding(mut self, winding: Winding) -> Self {
+        self.winding = winding;
+        self
+    }
+
+    pub fn with_material(mut self, material: Material) -> Self {
+        self.material = material;
+        self
+    }
+
+    // The triangles of the mesh, flipped where needed so that they're all counter-clockwise
+    pub fn counter_clockwise_triangles(&self) -> impl Iterator<Item = Triangle> + '_ {
+        self.triangles.iter().map(|t| match self.winding {
+            Winding::CounterClockwise => *t,
+            Winding::Clockwise => t.flipped(),
+        })
+    }
 }
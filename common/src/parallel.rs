@@ -0,0 +1,49 @@
+use std::{num::NonZeroUsize, sync::Mutex, thread};
+
+// Use all cores unless told otherwise
+pub fn default_threads() -> NonZeroUsize {
+    thread::available_parallelism().unwrap_or(NonZeroUsize::MIN)
+}
+
+// Calls `f` for every item, spread out over `threads` threads.
+// Items are handed out one by one as threads become available, so uneven workloads stay balanced.
+pub fn for_each<I, F>(items: I, threads: NonZeroUsize, f: F)
+where
+    I: Iterator + Send,
+    I::Item: Send,
+    F: Fn(I::Item) + Sync,
+{
+    if threads.get() == 1 {
+        items.for_each(f);
+        return;
+    }
+
+    let items = Mutex::new(items);
+    thread::scope(|scope| {
+        for _ in 0..threads.get() {
+            scope.spawn(|| {
+                loop {
+                    let item = items.lock().unwrap().next();
+                    match item {
+                        Some(item) => f(item),
+                        None => break,
+                    }
+                }
+            });
+        }
+    });
+}
+
+// Runs `a` and `b` at the same time, `b` on a new thread, and returns both results
+pub fn join<A, B, RA, RB>(a: A, b: B) -> (RA, RB)
+where
+    A: FnOnce() -> RA,
+    B: FnOnce() -> RB + Send,
+    RB: Send,
+{
+    thread::scope(|scope| {
+        let b = scope.spawn(b);
+        let a = a();
+        (a, b.join().unwrap())
+    })
+}
@@ -1,10 +1,41 @@
+use glam::Affine3A;
+
 use crate::{camera::Camera, light::Light, model::triangle::Mesh};
 
+// Which triangles don't get drawn, based on the winding order of the mesh they belong to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CullMode {
+    #[default]
+    None,
+    Back,
+    Front,
+}
+
+impl CullMode {
+    pub fn culls(&self, front_facing: bool) -> bool {
+        match self {
+            CullMode::None => false,
+            CullMode::Back => !front_facing,
+            CullMode::Front => front_facing,
+        }
+    }
+}
+
+// A copy of one of the scene's meshes, placed somewhere in the world. Instances of the same mesh
+// share its triangles.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Instance {
+    pub mesh: usize,         // Index into the scene's meshes
+    pub transform: Affine3A, // From the mesh's space to world space
+}
+
 #[derive(Default)]
 pub struct SceneBuilder {
     camera: Option<Camera>,
     lights: Vec<Light>,
     meshes: Vec<Mesh>,
+    instances: Vec<Instance>,
+    cull_mode: CullMode,
 }
 
 impl SceneBuilder {
@@ -17,13 +48,30 @@ impl SceneBuilder {
         self
     }
 
-    pub fn add_mesh(mut self, mesh: Mesh) -> Self {
-        self.meshes.push(mesh);
-        self
+    // Adds a mesh with a single instance, at the identity transform
+    pub fn add_mesh(self, mesh: Mesh) -> Self {
+        self.add_instanced_mesh(mesh, [Affine3A::IDENTITY])
     }
 
-    pub fn add_meshes(mut self, meshes: Vec<Mesh>) -> Self {
-        self.meshes.extend(meshes);
+    pub fn add_meshes(self, meshes: Vec<Mesh>) -> Self {
+        meshes
+            .into_iter()
+            .fold(self, |builder, mesh| builder.add_mesh(mesh))
+    }
+
+    // Adds a mesh once, with an instance for every transform
+    pub fn add_instanced_mesh(
+        mut self,
+        mesh: Mesh,
+        transforms: impl IntoIterator<Item = Affine3A>,
+    ) -> Self {
+        let index = self.meshes.len();
+        self.meshes.push(mesh);
+        self.instances
+            .extend(transforms.into_iter().map(|transform| Instance {
+                mesh: index,
+                transform,
+            }));
         self
     }
 
@@ -32,12 +80,19 @@ impl SceneBuilder {
         self
     }
 
+    pub fn with_cull_mode(mut self, cull_mode: CullMode) -> Self {
+        self.cull_mode = cull_mode;
+        self
+    }
+
     pub fn build(self) -> Scene {
         // We can do things like building acceleration structures here later
         Scene {
             camera: self.camera.unwrap_or_default(),
             meshes: self.meshes,
+            instances: self.instances,
             lights: self.lights,
+            cull_mode: self.cull_mode,
         }
     }
 }
@@ -46,7 +101,9 @@ impl SceneBuilder {
 pub struct Scene {
     camera: Camera,
     meshes: Vec<Mesh>,
+    instances: Vec<Instance>,
     lights: Vec<Light>,
+    cull_mode: CullMode,
 }
 
 impl Scene {
@@ -58,7 +115,20 @@ impl Scene {
         &self.meshes
     }
 
+    pub fn instances(&self) -> &Vec<Instance> {
+        &self.instances
+    }
+
+    // Moves an instance somewhere else
+    pub fn set_instance_transform(&mut self, instance: usize, transform: Affine3A) {
+        self.instances[instance].transform = transform;
+    }
+
     pub fn lights(&self) -> &Vec<Light> {
         &self.lights
     }
+
+    pub fn cull_mode(&self) -> CullMode {
+        self.cull_mode
+    }
 }
@@ -5,17 +5,64 @@ use format::RGBA8;
 /**
  * A simple surface representation.
  *
- * RGBA8 format, row-major order.
+ * RGBA8 format by default, row-major order.
  */
-pub struct Surface {
+pub struct Surface<P = RGBA8> {
     pub(crate) width: u32,
     pub(crate) height: u32,
-    pub(crate) buffer: Vec<RGBA8>,
+    pub(crate) buffer: Vec<P>,
 }
 
+/**
+ * NDC depth per pixel, from 0 (near plane) to 1 (far plane).
+ */
+pub type DepthBuffer = Surface<f32>;
+
 impl Surface {
     pub fn new(width: u32, height: u32) -> Self {
-        let buffer = vec![RGBA8::BLACK; width as usize * height as usize];
+        Self::filled(width, height, RGBA8::BLACK)
+    }
+}
+
+impl DepthBuffer {
+    /**
+     * Turns the depth buffer into a grayscale image, for debugging.
+     *
+     * The depth range that's actually in use is stretched to go from black (closest)
+     * to white (furthest). Pixels at the far plane, where nothing was drawn, are white.
+     */
+    pub fn to_grayscale(&self) -> Surface {
+        let (min, max) = self
+            .buffer
+            .iter()
+            .filter(|&&depth| depth < 1.0)
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), &depth| {
+                (min.min(depth), max.max(depth))
+            });
+        let range = (max - min).max(f32::EPSILON);
+
+        Surface {
+            width: self.width,
+            height: self.height,
+            buffer: self
+                .buffer
+                .iter()
+                .map(|&depth| {
+                    let value = if depth < 1.0 {
+                        (depth - min) / range
+                    } else {
+                        1.0
+                    };
+                    (glam::Vec3::ONE * value).into()
+                })
+                .collect(),
+        }
+    }
+}
+
+impl<P: Copy> Surface<P> {
+    pub fn filled(width: u32, height: u32, value: P) -> Self {
+        let buffer = vec![value; width as usize * height as usize];
         Surface {
             width,
             height,
@@ -31,17 +78,66 @@ impl Surface {
         self.height
     }
 
-    pub fn get(&self, x: u32, y: u32) -> RGBA8 {
+    pub fn get(&self, x: u32, y: u32) -> P {
         self.buffer[y as usize * self.width as usize + x as usize]
     }
 
-    pub fn get_mut(&mut self, x: u32, y: u32) -> &mut RGBA8 {
+    pub fn get_mut(&mut self, x: u32, y: u32) -> &mut P {
         &mut self.buffer[y as usize * self.width as usize + x as usize]
     }
 
-    pub fn clear(&mut self, color: RGBA8) {
+    pub fn clear(&mut self, value: P) {
         for pixel in &mut self.buffer {
-            *pixel = color;
+            *pixel = value;
         }
     }
+
+    /**
+     * Splits the surface into horizontal bands of `band_height` rows.
+     *
+     * The last band can be shorter than the others.
+     */
+    pub fn bands_mut(&mut self, band_height: u32) -> impl Iterator<Item = SurfaceBand<'_, P>> {
+        let width = self.width;
+        let band_size = (band_height as usize * width as usize).max(1);
+        self.buffer
+            .chunks_mut(band_size)
+            .enumerate()
+            .map(move |(i, buffer)| SurfaceBand {
+                y: i as u32 * band_height,
+                width,
+                buffer,
+            })
+    }
+}
+
+/**
+ * A horizontal band of rows of a surface.
+ *
+ * Bands don't overlap, so different threads can draw into different bands at the same time.
+ * Coordinates are the same as in the full surface.
+ */
+pub struct SurfaceBand<'a, P = RGBA8> {
+    y: u32,
+    width: u32,
+    buffer: &'a mut [P],
+}
+
+impl<P> SurfaceBand<'_, P> {
+    // The first row of the surface that's part of this band
+    pub fn y(&self) -> u32 {
+        self.y
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        (self.buffer.len() / self.width as usize) as u32
+    }
+
+    pub fn get_mut(&mut self, x: u32, y: u32) -> &mut P {
+        &mut self.buffer[(y - self.y) as usize * self.width as usize + x as usize]
+    }
 }
@@ -0,0 +1,95 @@
+use glam::{Vec2, Vec3};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WireframeMode {
+    // Only the edges of the visible triangles, on top of the background
+    Only,
+    // The edges on top of the normally shaded image
+    Overlay,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Wireframe {
+    pub mode: WireframeMode,
+    pub color: Vec3,
+    pub thickness: f32, // Line width, in pixels
+}
+
+impl Default for Wireframe {
+    fn default() -> Self {
+        Self {
+            mode: WireframeMode::Overlay,
+            color: Vec3::ZERO,
+            thickness: 1.0,
+        }
+    }
+}
+
+impl Wireframe {
+    // The final color of a pixel that's `edge_distance` pixels away from the closest edge
+    pub fn shade(&self, shaded: Vec3, background: Vec3, edge_distance: f32) -> Vec3 {
+        if edge_distance <= self.thickness / 2.0 {
+            self.color
+        } else {
+            match self.mode {
+                WireframeMode::Only => background,
+                WireframeMode::Overlay => shaded,
+            }
+        }
+    }
+}
+
+// Distance in pixels to the closest edge of a triangle, given the barycentric coordinates at a
+// pixel and at its neighbours one pixel to the right and one pixel down.
+// Barycentric coordinate i is 0 on the edge opposite of vertex i, so dividing it by how fast it
+// changes per pixel gives the distance to that edge in pixels. A coordinate that doesn't change at
+// all never gets to its edge, rather than dividing 0 by 0.
+pub fn edge_distance(barycentrics: Vec3, right: Vec3, down: Vec3) -> f32 {
+    [0, 1, 2]
+        .map(|i| {
+            let gradient = Vec2::new(right[i] - barycentrics[i], down[i] - barycentrics[i]);
+            if gradient == Vec2::ZERO {
+                f32::INFINITY
+            } else {
+                barycentrics[i] / gradient.length()
+            }
+        })
+        .into_iter()
+        .fold(f32::INFINITY, f32::min)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edge_distance() {
+        // Per pixel, the coordinates change by 0.1, 0.1 * sqrt(2) and 0.1
+        let barycentrics = Vec3::new(0.5, 0.3, 0.2);
+        let right = barycentrics + Vec3::new(-0.1, 0.1, 0.0);
+        let down = barycentrics + Vec3::new(0.0, -0.1, 0.1);
+        let distance = edge_distance(barycentrics, right, down);
+        assert!((distance - 2.0).abs() < 1e-5, "{distance}");
+    }
+
+    #[test]
+    fn test_edge_distance_without_gradient() {
+        // The third coordinate doesn't change, so only the other two edges count, even when the
+        // pixel is on the third one
+        for barycentrics in [Vec3::new(0.6, 0.4, 0.0), Vec3::new(0.4, 0.3, 0.3)] {
+            let right = barycentrics + Vec3::new(-0.1, 0.1, 0.0);
+            let down = barycentrics + Vec3::new(0.1, -0.1, 0.0);
+            let distance = edge_distance(barycentrics, right, down);
+            let expected = barycentrics.x.min(barycentrics.y) / 0.1_f32.hypot(0.1);
+            assert!(
+                (distance - expected).abs() < 1e-5,
+                "{distance} != {expected}"
+            );
+        }
+
+        // Nothing changes at all, so there's no edge anywhere
+        let barycentrics = Vec3::new(0.0, 0.5, 0.5);
+        let distance = edge_distance(barycentrics, barycentrics, barycentrics);
+        assert_eq!(distance, f32::INFINITY);
+    }
+}
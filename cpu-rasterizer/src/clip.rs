@@ -0,0 +1,236 @@
+use glam::{Vec3, Vec4};
+
+use crate::shader::Varyings;
+
+// The clip space frustum as 6 planes (a, b, c, d): a vertex lies inside of a plane when the dot
+// product of its (x, y, z, w) position with the plane is positive.
+// z goes from 0 (near) to w (far), matching the camera's projection matrix.
+const PLANES: [Vec4; 6] = [
+    Vec4::new(0.0, 0.0, 1.0, 0.0),  // Near: z >= 0
+    Vec4::new(0.0, 0.0, -1.0, 1.0), // Far: z <= w
+    Vec4::new(1.0, 0.0, 0.0, 1.0),  // Left: x >= -w
+    Vec4::new(-1.0, 0.0, 0.0, 1.0), // Right: x <= w
+    Vec4::new(0.0, 1.0, 0.0, 1.0),  // Bottom: y >= -w
+    Vec4::new(0.0, -1.0, 0.0, 1.0), // Top: y <= w
+];
+
+// Every plane can add at most one vertex to a convex polygon, so a clipped triangle has at most 3 + 6 vertices
+const MAX_VERTICES: usize = 3 + PLANES.len();
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ClipVertex<V> {
+    pub position: Vec4, // Clip space position
+    pub varyings: V,
+    // Barycentric coordinates within the original triangle, clipping can move vertices inwards
+    pub barycentrics: Vec3,
+}
+
+impl<V: Varyings> ClipVertex<V> {
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        Self {
+            position: self.position.lerp(other.position, t),
+            varyings: self.varyings.lerp(&other.varyings, t),
+            barycentrics: self.barycentrics.lerp(other.barycentrics, t),
+        }
+    }
+}
+
+// A convex polygon in clip space
+#[derive(Debug, Clone, Copy)]
+pub struct Polygon<V> {
+    vertices: [ClipVertex<V>; MAX_VERTICES],
+    len: usize,
+}
+
+impl<V: Varyings> Polygon<V> {
+    fn empty() -> Self {
+        Self {
+            vertices: [ClipVertex::default(); MAX_VERTICES],
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, vertex: ClipVertex<V>) {
+        // Rounding errors can make the polygon very slightly concave, which could in theory add
+        // more vertices than a convex polygon would. Those are too close to matter, so drop them.
+        if self.len < MAX_VERTICES {
+            self.vertices[self.len] = vertex;
+            self.len += 1;
+        }
+    }
+
+    pub fn vertices(&self) -> &[ClipVertex<V>] {
+        &self.vertices[..self.len]
+    }
+
+    // Splits the polygon back up into triangles, as a fan around the first vertex
+    pub fn triangles(&self) -> impl Iterator<Item = [ClipVertex<V>; 3]> + '_ {
+        let vertices = self.vertices();
+        (2..vertices.len()).map(|i| [vertices[0], vertices[i - 1], vertices[i]])
+    }
+}
+
+impl<V: Varyings> From<[ClipVertex<V>; 3]> for Polygon<V> {
+    fn from(triangle: [ClipVertex<V>; 3]) -> Self {
+        let mut polygon = Self::empty();
+        for vertex in triangle {
+            polygon.push(vertex);
+        }
+        polygon
+    }
+}
+
+// Clips a triangle against the view frustum using Sutherland-Hodgman.
+// Returns None if nothing of the triangle is left.
+pub fn clip_triangle<V: Varyings>(triangle: [ClipVertex<V>; 3]) -> Option<Polygon<V>> {
+    let mut polygon = Polygon::from(triangle);
+
+    for plane in PLANES {
+        let mut distances = [0.0; MAX_VERTICES];
+        for (distance, vertex) in distances.iter_mut().zip(polygon.vertices()) {
+            *distance = plane.dot(vertex.position);
+        }
+
+        if distances[..polygon.len].iter().all(|&d| d >= 0.0) {
+            // Entirely on the inside of this plane, nothing to clip
+            continue;
+        }
+
+        let mut clipped = Polygon::empty();
+        for i in 0..polygon.len {
+            let j = (i + 1) % polygon.len;
+            let (current, next) = (&polygon.vertices[i], &polygon.vertices[j]);
+            let (current_distance, next_distance) = (distances[i], distances[j]);
+
+            if current_distance >= 0.0 {
+                clipped.push(*current);
+            }
+            if (current_distance >= 0.0) != (next_distance >= 0.0) {
+                // This edge crosses the plane, add the intersection point
+                let t = current_distance / (current_distance - next_distance);
+                clipped.push(current.lerp(next, t));
+            }
+        }
+
+        if clipped.len < 3 {
+            return None;
+        }
+        polygon = clipped;
+    }
+
+    Some(polygon)
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec4Swizzles;
+
+    use super::*;
+
+    fn triangle(positions: [Vec4; 3]) -> [ClipVertex<()>; 3] {
+        let [v1, v2, v3] = positions;
+        [(v1, Vec3::X), (v2, Vec3::Y), (v3, Vec3::Z)].map(|(position, barycentrics)| ClipVertex {
+            position,
+            varyings: (),
+            barycentrics,
+        })
+    }
+
+    // Twice the signed area of a clip space triangle after the perspective divide
+    fn ndc_area(positions: [Vec4; 3]) -> f32 {
+        let [a, b, c] = positions.map(|p| p.xy() / p.w);
+        (b - a).perp_dot(c - a)
+    }
+
+    fn assert_inside_frustum(polygon: &Polygon<()>) {
+        for vertex in polygon.vertices() {
+            assert!(vertex.position.w > 0.0, "{vertex:?} is behind the eye");
+            for plane in PLANES {
+                assert!(
+                    plane.dot(vertex.position) >= -1e-5,
+                    "{vertex:?} is outside of {plane}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_inside_is_unchanged() {
+        let positions = [
+            Vec4::new(-0.5, -0.5, 0.5, 1.0),
+            Vec4::new(0.5, -0.5, 0.5, 1.0),
+            Vec4::new(0.0, 1.0, 1.5, 2.0),
+        ];
+        let polygon = clip_triangle(triangle(positions)).unwrap();
+
+        let vertices = polygon.vertices();
+        assert_eq!(vertices.len(), 3);
+        for (vertex, original) in vertices.iter().zip(triangle(positions)) {
+            assert_eq!(vertex.position, original.position);
+            assert_eq!(vertex.barycentrics, original.barycentrics);
+        }
+    }
+
+    #[test]
+    fn test_outside_is_none() {
+        // Right of the frustum
+        let right = [
+            Vec4::new(2.0, -0.5, 0.5, 1.0),
+            Vec4::new(3.0, -0.5, 0.5, 1.0),
+            Vec4::new(2.5, 0.5, 0.5, 1.0),
+        ];
+        assert!(clip_triangle(triangle(right)).is_none());
+
+        // Behind the eye
+        let behind = [
+            Vec4::new(-0.5, -0.5, -1.5, -1.0),
+            Vec4::new(0.5, -0.5, -1.5, -1.0),
+            Vec4::new(0.0, 0.5, -1.5, -1.0),
+        ];
+        assert!(clip_triangle(triangle(behind)).is_none());
+    }
+
+    #[test]
+    fn test_crossing_near_plane() {
+        let positions = [
+            Vec4::new(-0.5, -0.5, 0.5, 1.0),
+            Vec4::new(0.5, -0.5, 0.5, 1.0),
+            Vec4::new(0.0, 0.5, -0.5, 0.5),
+        ];
+        let polygon = clip_triangle(triangle(positions)).unwrap();
+
+        // The corner behind the near plane gets cut off, which leaves a quad
+        assert_eq!(polygon.vertices().len(), 4);
+        assert_inside_frustum(&polygon);
+    }
+
+    #[test]
+    fn test_vertex_behind_eye_does_not_invert() {
+        let positions = [
+            Vec4::new(-0.5, -0.5, 0.5, 1.0),
+            Vec4::new(0.5, -0.5, 0.5, 1.0),
+            Vec4::new(0.0, 1.0, -1.5, -1.0),
+        ];
+        // Dividing the vertex behind the eye by its negative w would mirror it through the
+        // center of the screen, and turn the triangle around
+        assert!(ndc_area(positions) < 0.0);
+
+        // A small part of the triangle that's in front of the eye shows which way it faces
+        let towards_back = positions[0].lerp(positions[1], 0.5).lerp(positions[2], 0.1);
+        let expected = ndc_area([positions[0], positions[1], towards_back]);
+        assert!(expected > 0.0);
+
+        let polygon = clip_triangle(triangle(positions)).unwrap();
+        assert_inside_frustum(&polygon);
+        for clipped in polygon.triangles() {
+            let area = ndc_area(clipped.map(|v| v.position));
+            assert!(area > 0.0, "{clipped:?} is facing the other way");
+        }
+
+        // Clipping moves vertices along the edges, so the barycentrics stay on the triangle
+        for vertex in polygon.vertices() {
+            assert!((vertex.barycentrics.element_sum() - 1.0).abs() < 1e-5);
+            assert!(vertex.barycentrics.min_element() >= 0.0);
+        }
+    }
+}
@@ -1,54 +1,467 @@
 use core::f32;
+use std::{marker::PhantomData, num::NonZeroUsize};
 
 use common::{
+    parallel,
     scene::Scene,
-    surface::{Surface, format::RGBA8},
+    surface::{DepthBuffer, Surface},
+    wireframe::Wireframe,
 };
+use glam::{Vec2, Vec3};
 
-pub struct CpuRasterizer {
+use crate::{
+    clip::clip_triangle,
+    msaa::Msaa,
+    screen::{ScreenTriangle, to_clip_space, to_screen_space},
+    shader::{
+        FragmentShader, LambertShader, LambertVaryings, SceneUniforms, Varyings, VertexShader,
+    },
+    tile::{TILE_SIZE, Tile, TileBins},
+};
+
+mod clip;
+pub mod msaa;
+mod screen;
+pub mod shader;
+mod tile;
+
+// Same sky color as the ray tracer uses for rays that don't hit anything
+const BACKGROUND: Vec3 = Vec3::new(0.5, 0.7, 0.9);
+
+// Renders the scene's meshes with a vertex and a fragment shader, which share the uniforms U and
+// pass the varyings V from one to the other
+pub struct CpuRasterizer<
+    U = SceneUniforms,
+    V = LambertVaryings,
+    VS = LambertShader,
+    FS = LambertShader,
+> {
     scene: Scene,
+    threads: NonZeroUsize,
+    msaa: Msaa,
+    wireframe: Option<Wireframe>,
+    // Always on, tests turn it off to check that it doesn't change anything
+    hierarchical_z: bool,
+    uniforms: U,
+    vertex_shader: VS,
+    fragment_shader: FS,
+    varyings: PhantomData<fn() -> V>,
 }
 
 impl CpuRasterizer {
     pub fn new(scene: Scene) -> Self {
-        Self { scene }
+        Self {
+            threads: parallel::default_threads(),
+            msaa: Msaa::default(),
+            wireframe: None,
+            hierarchical_z: true,
+            uniforms: SceneUniforms::new(&scene),
+            vertex_shader: LambertShader,
+            fragment_shader: LambertShader,
+            varyings: PhantomData,
+            scene,
+        }
+    }
+}
+
+impl<U, V, VS, FS> CpuRasterizer<U, V, VS, FS>
+where
+    U: Sync,
+    V: Varyings,
+    VS: VertexShader<U, V>,
+    FS: FragmentShader<U, V>,
+{
+    // Replaces the shaders and their uniforms.
+    // SceneUniforms::new gives the camera and lights of the scene, for shaders that need those.
+    pub fn with_shaders<U2, V2, VS2, FS2>(
+        self,
+        vertex_shader: VS2,
+        fragment_shader: FS2,
+        uniforms: U2,
+    ) -> CpuRasterizer<U2, V2, VS2, FS2>
+    where
+        U2: Sync,
+        V2: Varyings,
+        VS2: VertexShader<U2, V2>,
+        FS2: FragmentShader<U2, V2>,
+    {
+        CpuRasterizer {
+            scene: self.scene,
+            threads: self.threads,
+            msaa: self.msaa,
+            wireframe: self.wireframe,
+            hierarchical_z: self.hierarchical_z,
+            uniforms,
+            vertex_shader,
+            fragment_shader,
+            varyings: PhantomData,
+        }
+    }
+
+    pub fn scene(&self) -> &Scene {
+        &self.scene
+    }
+
+    pub fn with_threads(mut self, threads: NonZeroUsize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    pub fn with_msaa(mut self, msaa: Msaa) -> Self {
+        self.msaa = msaa;
+        self
+    }
+
+    pub fn with_wireframe(mut self, wireframe: Wireframe) -> Self {
+        self.wireframe = Some(wireframe);
+        self
     }
 
     pub fn render(&self, surface: &mut Surface) {
-        surface.clear(RGBA8::BLACK);
-
-        let width = surface.width() as f32;
-        let height = surface.height() as f32;
-        let camera = self.scene.camera();
-        let origin = camera.origin();
-
-        let mut min_ndc = glam::Vec2::MAX;
-        let mut max_ndc = glam::Vec2::MIN;
-
-        for y in 0..surface.height() {
-            for x in 0..surface.width() {
-                let ndc = glam::Vec2::new((x as f32 + 0.5) / (width), -(y as f32 + 0.5) / (height))
-                    * 2.0
-                    + glam::Vec2::new(-1.0, 1.0);
-                let direction = camera.ndc_to_viewing_direction(ndc);
-
-                min_ndc = min_ndc.min(ndc);
-                max_ndc = max_ndc.max(ndc);
-
-                let mut closest_intersection = f32::INFINITY;
-                for mesh in self.scene.meshes() {
-                    for triangle in &mesh.triangles {
-                        todo!()
-                        // if let Some((t, _, _)) = triangle.intersect(origin, direction)
-                        //     && t < closest_intersection
-                        // {
-                        //     // Add some shading!
-                        //     *surface.get_mut(x, y) = (glam::Vec3::ONE).into();
-                        //     closest_intersection = t;
-                        // }
+        let mut depth_buffer = DepthBuffer::filled(surface.width(), surface.height(), 1.0);
+        self.render_with_depth(surface, &mut depth_buffer);
+    }
+
+    // Like render, but also hands back the depth buffer
+    pub fn render_with_depth(&self, surface: &mut Surface, depth_buffer: &mut DepthBuffer) {
+        assert_eq!(
+            (surface.width(), surface.height()),
+            (depth_buffer.width(), depth_buffer.height()),
+            "The surface and depth buffer should be the same size"
+        );
+        let width = surface.width();
+        let height = surface.height();
+
+        // Transform, clip and project all triangles up front, then sort them into tiles
+        let triangles = self.setup_triangles(Vec2::new(width as f32, height as f32));
+        let bins = TileBins::new(width, height, &triangles);
+
+        // Every band of tiles is a separate part of the surface, so they can be drawn in parallel
+        let bands = surface
+            .bands_mut(TILE_SIZE)
+            .zip(depth_buffer.bands_mut(TILE_SIZE));
+        parallel::for_each(bands, self.threads, |(mut band, mut depth_band)| {
+            let tile_y = band.y() / TILE_SIZE;
+            for tile_x in 0..bins.tiles_x() {
+                let mut tile = Tile::new(&band, tile_x, self.msaa, BACKGROUND, self.hierarchical_z);
+                for &i in bins.get(tile_x, tile_y) {
+                    tile.rasterize(&triangles[i as usize], |fragment| {
+                        let color = self.fragment_shader.shade(&self.uniforms, fragment);
+                        match &self.wireframe {
+                            Some(wireframe) => {
+                                wireframe.shade(color, BACKGROUND, fragment.edge_distance())
+                            }
+                            None => color,
+                        }
+                    });
+                }
+                tile.resolve(&mut band, &mut depth_band);
+            }
+        });
+    }
+
+    fn setup_triangles(&self, size: Vec2) -> Vec<ScreenTriangle<V>> {
+        let cull_mode = self.scene.cull_mode();
+
+        let mut triangles = Vec::new();
+        for instance in self.scene.instances() {
+            let mesh = &self.scene.meshes()[instance.mesh];
+            for triangle in mesh.counter_clockwise_triangles() {
+                let triangle = triangle.transformed(&instance.transform);
+                let Some(polygon) = clip_triangle(to_clip_space(
+                    &self.vertex_shader,
+                    &self.uniforms,
+                    &triangle,
+                )) else {
+                    // Completely outside of the view frustum
+                    continue;
+                };
+
+                triangles.extend(
+                    polygon
+                        .triangles()
+                        .filter_map(|clipped_triangle| {
+                            ScreenTriangle::new(clipped_triangle.map(|v| to_screen_space(&v, size)))
+                        })
+                        .filter(|t| !cull_mode.culls(t.front_facing())),
+                );
+            }
+        }
+        triangles
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::{
+        camera::Camera,
+        light::Light,
+        model::{format::obj::load_obj, triangle::Vertex},
+        scene::{CullMode, SceneBuilder},
+        surface::format::RGBA8,
+    };
+    use cpu_ray_tracer::CpuRayTracer;
+    use glam::{Mat4, Vec4};
+
+    use super::*;
+    use crate::shader::Fragment;
+
+    const WIDTH: u32 = 96;
+    const HEIGHT: u32 = 54;
+
+    // One of the scenes in assets/scenes, seen from origin
+    fn scene_builder(name: &str, origin: Vec3, target: Vec3) -> SceneBuilder {
+        let meshes = load_obj(format!(
+            "{}/../assets/scenes/{name}/{name}.obj",
+            env!("CARGO_MANIFEST_DIR")
+        ));
+        SceneBuilder::new()
+            .with_camera(Camera::look_at(
+                origin,
+                target,
+                Vec3::Y,
+                80.0,
+                WIDTH as f32 / HEIGHT as f32,
+            ))
+            .add_meshes(meshes)
+            .add_light(Light::Sun {
+                direction: Vec3::ONE.normalize(),
+                intensity: 0.8,
+            })
+    }
+
+    fn cube() -> Scene {
+        scene_builder("cube", Vec3::new(2.0, 1.0, 1.0), Vec3::ZERO).build()
+    }
+
+    fn teapot() -> Scene {
+        scene_builder(
+            "teapot",
+            Vec3::new(100.0, 80.0, 80.0),
+            Vec3::new(5.0, 40.0, 0.0),
+        )
+        .build()
+    }
+
+    // Which pixels something was drawn on
+    fn coverage(surface: &Surface) -> Vec<bool> {
+        let background = RGBA8::from(BACKGROUND);
+        (0..surface.height())
+            .flat_map(|y| (0..surface.width()).map(move |x| (x, y)))
+            .map(|(x, y)| surface.get(x, y) != background)
+            .collect()
+    }
+
+    #[test]
+    fn test_silhouettes_match_ray_tracer() {
+        for scene in [cube, teapot] {
+            let mut rasterized = Surface::new(WIDTH, HEIGHT);
+            CpuRasterizer::new(scene()).render(&mut rasterized);
+            let mut ray_traced = Surface::new(WIDTH, HEIGHT);
+            CpuRayTracer::new(scene()).render(&mut ray_traced);
+
+            let rasterized = coverage(&rasterized);
+            let ray_traced = coverage(&ray_traced);
+            assert!(rasterized.iter().filter(|&&covered| covered).count() > 100);
+
+            // Pixels whose center is right on an edge can go either way
+            let on_edge = |x: u32, y: u32| {
+                let covered = |x: u32, y: u32| rasterized[(y * WIDTH + x) as usize];
+                [(-1, 0), (1, 0), (0, -1), (0, 1)].iter().any(|&(dx, dy)| {
+                    let (nx, ny) = (x as i32 + dx, y as i32 + dy);
+                    (0..WIDTH as i32).contains(&nx)
+                        && (0..HEIGHT as i32).contains(&ny)
+                        && covered(nx as u32, ny as u32) != covered(x, y)
+                })
+            };
+            let mut differing = 0;
+            for y in 0..HEIGHT {
+                for x in 0..WIDTH {
+                    let i = (y * WIDTH + x) as usize;
+                    if rasterized[i] != ray_traced[i] {
+                        assert!(
+                            on_edge(x, y),
+                            "pixel ({x}, {y}) differs inside a silhouette"
+                        );
+                        differing += 1;
                     }
                 }
             }
+            assert!(differing <= 10, "{differing} pixels differ");
+        }
+    }
+
+    #[test]
+    fn test_threads_match_single_threaded() {
+        for scene in [cube, teapot] {
+            let render = |threads| {
+                let mut surface = Surface::new(WIDTH, HEIGHT);
+                CpuRasterizer::new(scene())
+                    .with_threads(NonZeroUsize::new(threads).unwrap())
+                    .render(&mut surface);
+                surface
+            };
+
+            let single = render(1);
+            for threads in [2, 3, 8] {
+                let multi = render(threads);
+                for y in 0..HEIGHT {
+                    for x in 0..WIDTH {
+                        assert_eq!(single.get(x, y), multi.get(x, y), "pixel ({x}, {y})");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_cull_modes() {
+        let render = |origin: Vec3, cull_mode| {
+            let scene = scene_builder("cube", origin, Vec3::new(1.0, 0.0, 0.0))
+                .with_cull_mode(cull_mode)
+                .build();
+            let mut surface = Surface::new(WIDTH, HEIGHT);
+            let mut depth_buffer = DepthBuffer::filled(WIDTH, HEIGHT, 1.0);
+            CpuRasterizer::new(scene).render_with_depth(&mut surface, &mut depth_buffer);
+            (surface, depth_buffer)
+        };
+        let center = (WIDTH / 2, HEIGHT / 2);
+
+        // From outside of the cube, the front faces are the near ones and the back faces the far
+        // ones, so both cull modes leave the same silhouette at different depths
+        let outside = Vec3::new(-2.0, 0.0, 0.0);
+        let (none, none_depth) = render(outside, CullMode::None);
+        let (back, back_depth) = render(outside, CullMode::Back);
+        let (front, front_depth) = render(outside, CullMode::Front);
+        assert_eq!(coverage(&none), coverage(&back));
+        assert_eq!(coverage(&none), coverage(&front));
+        assert_eq!(
+            none_depth.get(center.0, center.1),
+            back_depth.get(center.0, center.1)
+        );
+        assert!(front_depth.get(center.0, center.1) > back_depth.get(center.0, center.1));
+
+        // From inside of it, every face is facing away
+        for (cull_mode, covered) in [
+            (CullMode::None, true),
+            (CullMode::Back, false),
+            (CullMode::Front, true),
+        ] {
+            let (surface, _) = render(Vec3::ZERO, cull_mode);
+            assert!(
+                coverage(&surface).iter().all(|&c| c == covered),
+                "{cull_mode:?} from inside"
+            );
+        }
+    }
+
+    #[test]
+    fn test_hierarchical_z_does_not_change_the_image() {
+        for msaa in [Msaa::Off, Msaa::X4] {
+            let render = |hierarchical_z| {
+                let mut renderer = CpuRasterizer::new(teapot()).with_msaa(msaa);
+                renderer.hierarchical_z = hierarchical_z;
+                let mut surface = Surface::new(WIDTH, HEIGHT);
+                let mut depth_buffer = DepthBuffer::filled(WIDTH, HEIGHT, 1.0);
+                renderer.render_with_depth(&mut surface, &mut depth_buffer);
+                (surface, depth_buffer)
+            };
+
+            let (surface, depth_buffer) = render(true);
+            let (expected_surface, expected_depth_buffer) = render(false);
+            for y in 0..HEIGHT {
+                for x in 0..WIDTH {
+                    assert_eq!(surface.get(x, y), expected_surface.get(x, y));
+                    assert_eq!(depth_buffer.get(x, y), expected_depth_buffer.get(x, y));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_depth_buffer() {
+        let (near, far) = (1.0, 10.0);
+        let origin = Vec3::new(0.0, 0.0, 3.0);
+        let camera = Camera::look_at(
+            origin,
+            Vec3::ZERO,
+            Vec3::Y,
+            80.0,
+            WIDTH as f32 / HEIGHT as f32,
+        )
+        .with_clip_planes(near, far);
+        let scene = scene_builder("cube", origin, Vec3::ZERO)
+            .with_camera(camera)
+            .build();
+
+        let mut surface = Surface::new(WIDTH, HEIGHT);
+        let mut depth_buffer = DepthBuffer::filled(WIDTH, HEIGHT, 1.0);
+        CpuRasterizer::new(scene).render_with_depth(&mut surface, &mut depth_buffer);
+
+        // The cube's front face faces the camera, 2.5 in front of it
+        let distance: f32 = 2.5;
+        let expected = far * (distance - near) / (distance * (far - near));
+        let depth = depth_buffer.get(WIDTH / 2, HEIGHT / 2);
+        assert!((depth - expected).abs() < 1e-5, "{depth} != {expected}");
+
+        // Nothing was drawn in the corners
+        assert_eq!(depth_buffer.get(0, 0), 1.0);
+    }
+
+    // Colors surfaces by their normal, scaled by a varying that's 1 everywhere
+    struct NormalShader;
+
+    struct NormalUniforms {
+        view_projection: Mat4,
+    }
+
+    impl VertexShader<NormalUniforms, (Vec3, f32)> for NormalShader {
+        fn shade(&self, uniforms: &NormalUniforms, vertex: &Vertex) -> (Vec4, (Vec3, f32)) {
+            (
+                uniforms.view_projection * vertex.position.extend(1.0),
+                (vertex.normal, 1.0),
+            )
+        }
+    }
+
+    impl FragmentShader<NormalUniforms, (Vec3, f32)> for NormalShader {
+        fn shade(&self, _: &NormalUniforms, fragment: &Fragment<(Vec3, f32)>) -> Vec3 {
+            let (normal, scale) = fragment.varyings();
+            (normal * 0.5 + 0.5) * scale
+        }
+    }
+
+    #[test]
+    fn test_custom_shader() {
+        let scene = cube();
+        let uniforms = NormalUniforms {
+            view_projection: scene.camera().view_projection_matrix(),
+        };
+        let renderer = CpuRasterizer::new(scene).with_shaders(NormalShader, NormalShader, uniforms);
+        let mut surface = Surface::new(WIDTH, HEIGHT);
+        renderer.render(&mut surface);
+
+        // The camera sees the +x, +y and +z faces, which all have a normal of their own
+        let faces = [Vec3::X, Vec3::Y, Vec3::Z].map(|normal| RGBA8::from(normal * 0.5 + 0.5));
+        let mut seen = [false; 3];
+        let background = RGBA8::from(BACKGROUND);
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let color = surface.get(x, y);
+                if color == background {
+                    continue;
+                }
+                let face = faces.iter().position(|face| {
+                    [(face.r, color.r), (face.g, color.g), (face.b, color.b)]
+                        .iter()
+                        .all(|(a, b)| a.abs_diff(*b) <= 1)
+                });
+                let Some(face) = face else {
+                    panic!("pixel ({x}, {y}) is {color:?}, which isn't a face's normal");
+                };
+                seen[face] = true;
+            }
         }
+        assert_eq!(seen, [true; 3]);
     }
 }
@@ -0,0 +1,116 @@
+use glam::Vec2;
+
+// Number of coverage/depth samples per pixel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Msaa {
+    #[default]
+    Off,
+    X2,
+    X4,
+    X8,
+}
+
+impl Msaa {
+    // Sample positions relative to the pixel center, in pixels.
+    // These are the standard Direct3D sample patterns, which are given in 1/16th of a pixel.
+    pub fn sample_positions(&self) -> &'static [Vec2] {
+        const fn sample(x: f32, y: f32) -> Vec2 {
+            Vec2::new(x / 16.0, y / 16.0)
+        }
+
+        const OFF: [Vec2; 1] = [sample(0.0, 0.0)];
+        const X2: [Vec2; 2] = [sample(4.0, 4.0), sample(-4.0, -4.0)];
+        const X4: [Vec2; 4] = [
+            sample(-2.0, -6.0),
+            sample(6.0, -2.0),
+            sample(-6.0, 2.0),
+            sample(2.0, 6.0),
+        ];
+        const X8: [Vec2; 8] = [
+            sample(1.0, -3.0),
+            sample(-1.0, 3.0),
+            sample(5.0, 1.0),
+            sample(-3.0, -5.0),
+            sample(-5.0, 5.0),
+            sample(-7.0, -1.0),
+            sample(3.0, 7.0),
+            sample(7.0, -7.0),
+        ];
+
+        match self {
+            Msaa::Off => &OFF,
+            Msaa::X2 => &X2,
+            Msaa::X4 => &X4,
+            Msaa::X8 => &X8,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::surface::{DepthBuffer, Surface, format::RGBA8};
+    use glam::Vec3;
+
+    use super::*;
+    use crate::{
+        screen::{ScreenTriangle, ScreenVertex},
+        tile::{TILE_SIZE, Tile},
+    };
+
+    const ALL: [Msaa; 4] = [Msaa::Off, Msaa::X2, Msaa::X4, Msaa::X8];
+
+    #[test]
+    fn test_sample_positions() {
+        for (msaa, count) in ALL.into_iter().zip([1, 2, 4, 8]) {
+            let samples = msaa.sample_positions();
+            assert_eq!(samples.len(), count, "{msaa:?}");
+            for (i, sample) in samples.iter().enumerate() {
+                assert!(sample.abs().max_element() < 0.5, "{msaa:?} {sample}");
+                assert!(!samples[..i].contains(sample), "{msaa:?} {sample}");
+            }
+        }
+        // Without MSAA, pixels are sampled at their centers
+        assert_eq!(Msaa::Off.sample_positions(), [Vec2::ZERO]);
+    }
+
+    #[test]
+    fn test_resolve_blends_by_coverage() {
+        // A white triangle whose right edge runs down through the pixel at (4, 4), a bit to the
+        // right of its center, on a black background. It passes between the samples, which are
+        // on a grid of 1/16th of a pixel.
+        let edge = 4.5 + 2.5 / 16.0;
+        let vertex = |x, y| ScreenVertex {
+            position: Vec2::new(x, y),
+            depth: 0.5,
+            inv_w: 1.0,
+            varyings: (),
+            barycentrics: Vec3::ZERO,
+        };
+        let triangle = ScreenTriangle::new([
+            vertex(-100.0, -100.0),
+            vertex(edge, -100.0),
+            vertex(edge, 100.0),
+        ])
+        .unwrap();
+
+        for msaa in ALL {
+            let mut surface = Surface::new(8, 8);
+            let mut depth_buffer = DepthBuffer::filled(8, 8, 1.0);
+            let mut band = surface.bands_mut(TILE_SIZE).next().unwrap();
+            let mut depth_band = depth_buffer.bands_mut(TILE_SIZE).next().unwrap();
+            let mut tile = Tile::new(&band, 0, msaa, Vec3::ZERO, true);
+            tile.rasterize(&triangle, |_| Vec3::ONE);
+            tile.resolve(&mut band, &mut depth_band);
+
+            // Without MSAA, that's just whether the pixel's center is covered
+            let samples = msaa.sample_positions();
+            let covered = samples.iter().filter(|s| 4.5 + s.x < edge).count();
+            let expected = RGBA8::from(Vec3::splat(covered as f32 / samples.len() as f32));
+            assert_eq!(surface.get(4, 4), expected, "{msaa:?}");
+
+            // Pixels that are entirely on one side aren't blended
+            assert_eq!(surface.get(3, 4), RGBA8::from(Vec3::ONE), "{msaa:?}");
+            assert_eq!(surface.get(5, 4), RGBA8::from(Vec3::ZERO), "{msaa:?}");
+        }
+    }
+}
@@ -0,0 +1,240 @@
+use common::{model::triangle::Triangle, wireframe::edge_distance};
+use glam::{Vec2, Vec3, Vec4Swizzles};
+
+use crate::{
+    clip::ClipVertex,
+    shader::{Varyings, VertexShader},
+};
+
+// A vertex after projecting it onto the screen
+#[derive(Debug, Clone, Copy)]
+pub struct ScreenVertex<V> {
+    pub position: Vec2, // In pixels, (0, 0) is the top left corner
+    pub depth: f32,     // NDC depth, 0 on the near plane and 1 on the far plane
+    pub inv_w: f32,     // 1/w, for perspective correct interpolation
+    pub varyings: V,
+    pub barycentrics: Vec3, // Within the original, unclipped triangle
+}
+
+// A triangle that's ready to be rasterized
+#[derive(Debug, Clone, Copy)]
+pub struct ScreenTriangle<V> {
+    pub vertices: [ScreenVertex<V>; 3],
+    pub area: f32, // Twice the signed area, in pixels
+    // Bounding box of the triangle, in pixels
+    pub min: Vec2,
+    pub max: Vec2,
+    pub min_depth: f32, // Depth of the closest vertex
+}
+
+impl<V: Varyings> ScreenTriangle<V> {
+    // Returns None for degenerate triangles, since there's nothing to draw
+    pub fn new(vertices: [ScreenVertex<V>; 3]) -> Option<Self> {
+        let [v1, v2, v3] = vertices.map(|v| v.position);
+        let area = edge_function(v1, v2, v3);
+        if area == 0.0 {
+            return None;
+        }
+
+        Some(Self {
+            vertices,
+            area,
+            min: v1.min(v2).min(v3),
+            max: v1.max(v2).max(v3),
+            min_depth: vertices.map(|v| v.depth).into_iter().fold(1.0, f32::min),
+        })
+    }
+
+    // Only valid for counter-clockwise triangles. Since y points down in screen space,
+    // those have a negative area when they're facing the camera.
+    pub fn front_facing(&self) -> bool {
+        self.area < 0.0
+    }
+
+    // Barycentric coordinates of a point in screen space.
+    // Dividing by the signed area makes these positive inside the triangle, regardless of the
+    // winding order.
+    pub fn barycentrics(&self, p: Vec2) -> Vec3 {
+        let [v1, v2, v3] = self.vertices.map(|v| v.position);
+        Vec3::new(
+            edge_function(v2, v3, p),
+            edge_function(v3, v1, p),
+            edge_function(v1, v2, p),
+        ) / self.area
+    }
+
+    // Turns screen space barycentric coordinates into weights for interpolating the attributes.
+    // Those are linear in world space, not in screen space: interpolate them divided by w,
+    // and divide the result by the interpolated 1/w.
+    fn perspective_weights(&self, barycentrics: Vec3) -> Vec3 {
+        let weights = barycentrics * Vec3::from_array(self.vertices.map(|v| v.inv_w));
+        weights / weights.element_sum()
+    }
+}
+
+// A pixel (or part of one) that's covered by a triangle, the input of the fragment shader
+pub struct Fragment<'a, V> {
+    triangle: &'a ScreenTriangle<V>,
+    position: Vec2,     // Where in the pixel the fragment is shaded
+    barycentrics: Vec3, // Screen space barycentric coordinates of the position
+    weights: Vec3,      // Perspective correct interpolation weights
+}
+
+impl<'a, V: Varyings> Fragment<'a, V> {
+    pub(crate) fn new(triangle: &'a ScreenTriangle<V>, position: Vec2, barycentrics: Vec3) -> Self {
+        Self {
+            triangle,
+            position,
+            barycentrics,
+            weights: triangle.perspective_weights(barycentrics),
+        }
+    }
+
+    // The vertex shader's outputs, interpolated perspective-correctly
+    pub fn varyings(&self) -> V {
+        let [v1, v2, v3] = &self.triangle.vertices;
+        V::interpolate([&v1.varyings, &v2.varyings, &v3.varyings], self.weights)
+    }
+
+    // In pixels, (0, 0) is the top left corner of the surface
+    pub fn position(&self) -> Vec2 {
+        self.position
+    }
+
+    // NDC depth, 0 on the near plane and 1 on the far plane
+    pub fn depth(&self) -> f32 {
+        let [v1, v2, v3] = &self.triangle.vertices;
+        Vec3::new(v1.depth, v2.depth, v3.depth).dot(self.barycentrics)
+    }
+
+    // Only meaningful when the mesh's winding order is right
+    pub fn front_facing(&self) -> bool {
+        self.triangle.front_facing()
+    }
+
+    // Distance in pixels to the closest edge of the original triangle. Edges that were
+    // added by clipping don't count.
+    pub fn edge_distance(&self) -> f32 {
+        let [v1, v2, v3] = &self.triangle.vertices;
+        let original_barycentrics = |position: Vec2| {
+            let weights = self
+                .triangle
+                .perspective_weights(self.triangle.barycentrics(position));
+            v1.barycentrics * weights.x + v2.barycentrics * weights.y + v3.barycentrics * weights.z
+        };
+
+        edge_distance(
+            original_barycentrics(self.position),
+            original_barycentrics(self.position + Vec2::X),
+            original_barycentrics(self.position + Vec2::Y),
+        )
+    }
+}
+
+// Runs the vertex shader on every vertex of the triangle
+pub fn to_clip_space<U, V: Varyings>(
+    shader: &impl VertexShader<U, V>,
+    uniforms: &U,
+    triangle: &Triangle,
+) -> [ClipVertex<V>; 3] {
+    [
+        (&triangle.v1, Vec3::X),
+        (&triangle.v2, Vec3::Y),
+        (&triangle.v3, Vec3::Z),
+    ]
+    .map(|(vertex, barycentrics)| {
+        let (position, varyings) = shader.shade(uniforms, vertex);
+        ClipVertex {
+            position,
+            varyings,
+            barycentrics,
+        }
+    })
+}
+
+// Does the perspective divide and maps NDC onto the surface.
+// Only valid for clipped vertices, which are guaranteed to have a positive w.
+pub fn to_screen_space<V: Copy>(vertex: &ClipVertex<V>, size: Vec2) -> ScreenVertex<V> {
+    let ndc = vertex.position.xyz() / vertex.position.w;
+    ScreenVertex {
+        position: (ndc.truncate() * Vec2::new(0.5, -0.5) + 0.5) * size,
+        depth: ndc.z,
+        inv_w: 1.0 / vertex.position.w,
+        varyings: vertex.varyings,
+        barycentrics: vertex.barycentrics,
+    }
+}
+
+// Twice the signed area of the triangle (a, b, p)
+pub fn edge_function(a: Vec2, b: Vec2, p: Vec2) -> f32 {
+    (b - a).perp_dot(p - a)
+}
+
+#[cfg(test)]
+mod tests {
+    use common::{camera::Camera, model::triangle::Vertex};
+    use glam::{Mat4, Vec4};
+
+    use super::*;
+
+    // Passes the texture coordinates through
+    struct UvShader;
+
+    impl VertexShader<Mat4, Vec2> for UvShader {
+        fn shade(&self, view_projection: &Mat4, vertex: &Vertex) -> (Vec4, Vec2) {
+            (
+                *view_projection * vertex.position.extend(1.0),
+                vertex.uv.unwrap(),
+            )
+        }
+    }
+
+    #[test]
+    fn test_perspective_correct_uvs() {
+        let size = Vec2::new(160.0, 90.0);
+        let camera = Camera::new(Vec3::ZERO, Vec3::NEG_Z, Vec3::Y, 90.0, size.x / size.y);
+        // Stretches from right in front of the camera far into the distance
+        let vertex = |position, uv| Vertex {
+            position,
+            normal: Vec3::Z,
+            uv: Some(uv),
+        };
+        let triangle = Triangle {
+            v1: vertex(Vec3::new(-1.0, -1.0, -1.5), Vec2::new(0.0, 0.0)),
+            v2: vertex(Vec3::new(1.0, -1.0, -1.5), Vec2::new(1.0, 0.0)),
+            v3: vertex(Vec3::new(0.0, 10.0, -30.0), Vec2::new(0.5, 1.0)),
+        };
+
+        let clip_vertices = to_clip_space(&UvShader, &camera.view_projection_matrix(), &triangle);
+        let screen = ScreenTriangle::new(clip_vertices.map(|v| to_screen_space(&v, size))).unwrap();
+
+        // Halfway between the vertices on the screen, which is far from halfway in the world
+        let p = screen.vertices.map(|v| v.position).iter().sum::<Vec2>() / 3.0;
+        let barycentrics = screen.barycentrics(p);
+        let uv = Fragment::new(&screen, p, barycentrics).varyings();
+
+        // Where the camera ray through p hits the triangle
+        let ndc = (p / size - 0.5) * Vec2::new(2.0, -2.0);
+        let direction = camera.ndc_to_viewing_direction(ndc);
+        let [p1, p2, p3] = [triangle.v1, triangle.v2, triangle.v3].map(|v| v.position);
+        let normal = (p2 - p1).cross(p3 - p1);
+        let hit = direction * (p1.dot(normal) / direction.dot(normal));
+        let area = |a: Vec3, b: Vec3, c: Vec3| (b - a).cross(c - a).dot(normal);
+        let hit_barycentrics = Vec3::new(area(hit, p2, p3), area(p1, hit, p3), area(p1, p2, hit))
+            / normal.length_squared();
+        let expected = Vec2::interpolate(
+            [
+                &triangle.v1.uv.unwrap(),
+                &triangle.v2.uv.unwrap(),
+                &triangle.v3.uv.unwrap(),
+            ],
+            hit_barycentrics,
+        );
+        assert!(uv.distance(expected) < 1e-3, "{uv} != {expected}");
+
+        // Interpolating linearly in screen space gets it wrong
+        let [v1, v2, v3] = &screen.vertices;
+        let affine = Vec2::interpolate([&v1.varyings, &v2.varyings, &v3.varyings], barycentrics);
+        assert!(affine.distance(expected) > 0.1, "{affine} ~= {expected}");
+    }
+}
@@ -0,0 +1,125 @@
+use common::{light::Light, model::triangle::Vertex, scene::Scene};
+use glam::{Mat4, Vec2, Vec3, Vec4};
+
+pub use crate::screen::Fragment;
+
+// Values that the vertex shader outputs per vertex, and that get interpolated over the triangle
+// for the fragment shader.
+pub trait Varyings: Copy + Default + Send + Sync {
+    // The weighted sum of the varyings of a triangle's three vertices
+    fn interpolate(varyings: [&Self; 3], weights: Vec3) -> Self;
+
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        Self::interpolate([self, other, self], Vec3::new(1.0 - t, t, 0.0))
+    }
+}
+
+impl Varyings for () {
+    fn interpolate(_: [&Self; 3], _: Vec3) -> Self {}
+}
+
+macro_rules! impl_varyings_for_vector {
+    ($($t:ty),*) => {
+        $(
+            impl Varyings for $t {
+                fn interpolate([v1, v2, v3]: [&Self; 3], weights: Vec3) -> Self {
+                    *v1 * weights.x + *v2 * weights.y + *v3 * weights.z
+                }
+            }
+        )*
+    };
+}
+impl_varyings_for_vector!(f32, Vec2, Vec3, Vec4);
+
+// Tuples of varyings, so shaders don't need a struct for every combination
+macro_rules! impl_varyings_for_tuple {
+    ($($name:ident: $index:tt),*) => {
+        impl<$($name: Varyings),*> Varyings for ($($name,)*) {
+            fn interpolate([v1, v2, v3]: [&Self; 3], weights: Vec3) -> Self {
+                ($($name::interpolate([&v1.$index, &v2.$index, &v3.$index], weights),)*)
+            }
+        }
+    };
+}
+impl_varyings_for_tuple!(A: 0, B: 1);
+impl_varyings_for_tuple!(A: 0, B: 1, C: 2);
+impl_varyings_for_tuple!(A: 0, B: 1, C: 2, D: 3);
+
+// Runs once for every vertex of every triangle.
+// Returns the clip space position of the vertex, plus the varyings to interpolate.
+pub trait VertexShader<U, V: Varyings>: Sync {
+    fn shade(&self, uniforms: &U, vertex: &Vertex) -> (Vec4, V);
+}
+
+// Runs once for every pixel that a triangle covers, returns its color
+pub trait FragmentShader<U, V: Varyings>: Sync {
+    fn shade(&self, uniforms: &U, fragment: &Fragment<V>) -> Vec3;
+}
+
+// The uniforms that the built-in shaders use, taken from the scene
+#[derive(Debug, Clone)]
+pub struct SceneUniforms {
+    pub view_projection: Mat4,
+    pub lights: Vec<Light>,
+}
+
+impl SceneUniforms {
+    pub fn new(scene: &Scene) -> Self {
+        Self {
+            view_projection: scene.camera().view_projection_matrix(),
+            lights: scene.lights().to_vec(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LambertVaryings {
+    pub normal: Vec3,
+    pub uv: Vec2,
+}
+
+impl Varyings for LambertVaryings {
+    fn interpolate([v1, v2, v3]: [&Self; 3], weights: Vec3) -> Self {
+        Self {
+            normal: Vec3::interpolate([&v1.normal, &v2.normal, &v3.normal], weights),
+            uv: Vec2::interpolate([&v1.uv, &v2.uv, &v3.uv], weights),
+        }
+    }
+}
+
+// Same Lambert + checkerboard shading as the ray tracer, without the shadows
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LambertShader;
+
+impl VertexShader<SceneUniforms, LambertVaryings> for LambertShader {
+    fn shade(&self, uniforms: &SceneUniforms, vertex: &Vertex) -> (Vec4, LambertVaryings) {
+        (
+            uniforms.view_projection * vertex.position.extend(1.0),
+            LambertVaryings {
+                normal: vertex.normal,
+                uv: vertex.uv.unwrap_or(Vec2::ZERO),
+            },
+        )
+    }
+}
+
+impl FragmentShader<SceneUniforms, LambertVaryings> for LambertShader {
+    fn shade(&self, uniforms: &SceneUniforms, fragment: &Fragment<LambertVaryings>) -> Vec3 {
+        let LambertVaryings { normal, uv } = fragment.varyings();
+
+        let light_intensity: f32 = uniforms
+            .lights
+            .iter()
+            .map(|light| match light {
+                Light::Sun {
+                    direction,
+                    intensity,
+                } => intensity * normal.dot(direction.normalize()).clamp(0.0, 1.0),
+            })
+            .sum();
+
+        let color = ((uv.x * 16.0).round() + (uv.y * 16.0).round()) % 2.0;
+
+        Vec3::ONE * (0.5 + color / 2.0) * light_intensity
+    }
+}
@@ -0,0 +1,324 @@
+use std::ops::Range;
+
+use common::surface::SurfaceBand;
+use glam::{Vec2, Vec3};
+
+use crate::{
+    msaa::Msaa,
+    screen::{Fragment, ScreenTriangle},
+    shader::Varyings,
+};
+
+pub const TILE_SIZE: u32 = 64;
+
+// For every tile, the indices of the triangles that overlap it, in submission order
+pub struct TileBins {
+    tiles_x: u32,
+    bins: Vec<Vec<u32>>,
+}
+
+impl TileBins {
+    pub fn new<V>(width: u32, height: u32, triangles: &[ScreenTriangle<V>]) -> Self {
+        let tiles_x = width.div_ceil(TILE_SIZE);
+        let tiles_y = height.div_ceil(TILE_SIZE);
+        let mut bins = vec![Vec::new(); tiles_x as usize * tiles_y as usize];
+
+        let max_tile = Vec2::new(tiles_x as f32 - 1.0, tiles_y as f32 - 1.0);
+        for (i, triangle) in triangles.iter().enumerate() {
+            // Clipping keeps the triangles on the surface, clamping only guards against rounding
+            let min = (triangle.min / TILE_SIZE as f32)
+                .floor()
+                .clamp(Vec2::ZERO, max_tile);
+            let max = (triangle.max / TILE_SIZE as f32)
+                .floor()
+                .clamp(Vec2::ZERO, max_tile);
+
+            for tile_y in min.y as u32..=max.y as u32 {
+                for tile_x in min.x as u32..=max.x as u32 {
+                    bins[(tile_y * tiles_x + tile_x) as usize].push(i as u32);
+                }
+            }
+        }
+
+        Self { tiles_x, bins }
+    }
+
+    pub fn tiles_x(&self) -> u32 {
+        self.tiles_x
+    }
+
+    pub fn get(&self, tile_x: u32, tile_y: u32) -> &[u32] {
+        &self.bins[(tile_y * self.tiles_x + tile_x) as usize]
+    }
+}
+
+// Besides the depth of every sample, tiles keep track of the furthest depth in every block of
+// BLOCK_SIZE x BLOCK_SIZE pixels, and in the tile as a whole. Triangles that are completely behind
+// those can be skipped without looking at the individual samples.
+const BLOCK_SIZE: u32 = 8;
+const BLOCKS_PER_ROW: u32 = TILE_SIZE / BLOCK_SIZE;
+
+// A tile of the surface that's being rasterized.
+// Color and depth are kept per sample, until they're resolved into the surface.
+pub struct Tile {
+    // Pixel bounds of the tile, min inclusive and max exclusive
+    min: (u32, u32),
+    max: (u32, u32),
+    samples: &'static [Vec2],
+    color_buffer: Vec<Vec3>,
+    depth_buffer: Vec<f32>,
+    block_max_depth: Vec<f32>,
+    max_depth: f32,
+    hierarchical_z: bool, // Whether to skip triangles that are behind the max depths
+}
+
+impl Tile {
+    // The tile_x-th tile (from the left) of a band of tiles
+    pub fn new(
+        band: &SurfaceBand,
+        tile_x: u32,
+        msaa: Msaa,
+        background: Vec3,
+        hierarchical_z: bool,
+    ) -> Self {
+        let min = (tile_x * TILE_SIZE, band.y());
+        let max = (
+            (min.0 + TILE_SIZE).min(band.width()),
+            band.y() + band.height(),
+        );
+        let samples = msaa.sample_positions();
+        let num_samples = TILE_SIZE as usize * TILE_SIZE as usize * samples.len();
+        Self {
+            min,
+            max,
+            samples,
+            color_buffer: vec![background; num_samples],
+            depth_buffer: vec![1.0; num_samples],
+            block_max_depth: vec![1.0; BLOCKS_PER_ROW as usize * BLOCKS_PER_ROW as usize],
+            max_depth: 1.0,
+            hierarchical_z,
+        }
+    }
+
+    pub fn rasterize<V: Varyings, S: Fn(&Fragment<V>) -> Vec3>(
+        &mut self,
+        triangle: &ScreenTriangle<V>,
+        shade: S,
+    ) {
+        if self.hierarchical_z && triangle.min_depth >= self.max_depth {
+            // Completely hidden behind what's already in this tile
+            return;
+        }
+
+        // Only walk the part of the bounding box that's inside this tile
+        let min = triangle
+            .min
+            .floor()
+            .max(Vec2::new(self.min.0 as f32, self.min.1 as f32));
+        let max = triangle
+            .max
+            .ceil()
+            .min(Vec2::new(self.max.0 as f32, self.max.1 as f32));
+        if min.x >= max.x || min.y >= max.y {
+            return;
+        }
+        let (min_x, min_y) = (min.x as u32, min.y as u32);
+        let (max_x, max_y) = (max.x as u32, max.y as u32);
+
+        let mut drawn = false;
+        for block_y in (min_y - self.min.1) / BLOCK_SIZE..=(max_y - 1 - self.min.1) / BLOCK_SIZE {
+            for block_x in (min_x - self.min.0) / BLOCK_SIZE..=(max_x - 1 - self.min.0) / BLOCK_SIZE
+            {
+                let block = (block_y * BLOCKS_PER_ROW + block_x) as usize;
+                if self.hierarchical_z && triangle.min_depth >= self.block_max_depth[block] {
+                    // Completely hidden behind what's already in this block
+                    continue;
+                }
+
+                let block_min = (
+                    self.min.0 + block_x * BLOCK_SIZE,
+                    self.min.1 + block_y * BLOCK_SIZE,
+                );
+                let x_range = block_min.0.max(min_x)..(block_min.0 + BLOCK_SIZE).min(max_x);
+                let y_range = block_min.1.max(min_y)..(block_min.1 + BLOCK_SIZE).min(max_y);
+
+                if self.rasterize_pixels(triangle, x_range, y_range, &shade) {
+                    self.block_max_depth[block] = self.compute_block_max_depth(block_x, block_y);
+                    drawn = true;
+                }
+            }
+        }
+
+        if drawn {
+            self.max_depth = self.block_max_depth.iter().copied().fold(0.0, f32::max);
+        }
+    }
+
+    // Returns whether any samples were drawn
+    fn rasterize_pixels<V: Varyings, S: Fn(&Fragment<V>) -> Vec3>(
+        &mut self,
+        triangle: &ScreenTriangle<V>,
+        x_range: Range<u32>,
+        y_range: Range<u32>,
+        shade: &S,
+    ) -> bool {
+        let [v1, v2, v3] = &triangle.vertices;
+        let mut drawn = false;
+
+        for y in y_range {
+            for x in x_range.clone() {
+                let first_sample = self.index(x, y);
+                let center = Vec2::new(x as f32 + 0.5, y as f32 + 0.5);
+
+                // Coverage and depth are tested per sample, but the pixel only gets shaded once
+                let mut covered: u32 = 0;
+                let mut shading_sample = None;
+
+                for (i, offset) in self.samples.iter().enumerate() {
+                    let p = center + *offset;
+                    let b = triangle.barycentrics(p);
+                    if b.min_element() < 0.0 {
+                        continue;
+                    }
+
+                    // Early depth test, before interpolating any of the other attributes.
+                    // NDC depth is linear in screen space, so it doesn't need perspective correction.
+                    let depth = b.x * v1.depth + b.y * v2.depth + b.z * v3.depth;
+                    if depth >= self.depth_buffer[first_sample + i] {
+                        continue;
+                    }
+                    self.depth_buffer[first_sample + i] = depth;
+                    covered |= 1 << i;
+
+                    // Shade at the first visible sample, which is always inside the triangle
+                    shading_sample.get_or_insert((p, b));
+                }
+
+                let Some((p, b)) = shading_sample else {
+                    continue;
+                };
+                drawn = true;
+
+                let color = shade(&Fragment::new(triangle, p, b));
+
+                for i in 0..self.samples.len() {
+                    if covered & (1 << i) != 0 {
+                        self.color_buffer[first_sample + i] = color;
+                    }
+                }
+            }
+        }
+
+        drawn
+    }
+
+    fn compute_block_max_depth(&self, block_x: u32, block_y: u32) -> f32 {
+        let num_samples = self.samples.len();
+        let mut max_depth: f32 = 0.0;
+        for y in 0..BLOCK_SIZE {
+            let start = ((block_y * BLOCK_SIZE + y) * TILE_SIZE + block_x * BLOCK_SIZE) as usize
+                * num_samples;
+            let end = start + BLOCK_SIZE as usize * num_samples;
+            for &depth in &self.depth_buffer[start..end] {
+                max_depth = max_depth.max(depth);
+            }
+        }
+        max_depth
+    }
+
+    // Averages the samples of every pixel into the matching bands of the surface and depth buffer.
+    // The depth buffer gets the closest sample of every pixel.
+    pub fn resolve(&self, band: &mut SurfaceBand, depth_band: &mut SurfaceBand<f32>) {
+        let num_samples = self.samples.len();
+        for y in self.min.1..self.max.1 {
+            for x in self.min.0..self.max.0 {
+                let samples = self.index(x, y)..self.index(x, y) + num_samples;
+
+                let color = self.color_buffer[samples.clone()].iter().sum::<Vec3>();
+                *band.get_mut(x, y) = (color / num_samples as f32).into();
+
+                let depth = self.depth_buffer[samples]
+                    .iter()
+                    .copied()
+                    .fold(1.0, f32::min);
+                *depth_band.get_mut(x, y) = depth;
+            }
+        }
+    }
+
+    // Index of the first sample of a pixel
+    fn index(&self, x: u32, y: u32) -> usize {
+        ((y - self.min.1) * TILE_SIZE + (x - self.min.0)) as usize * self.samples.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::screen::ScreenVertex;
+
+    use super::*;
+
+    fn triangle(positions: [Vec2; 3]) -> ScreenTriangle<()> {
+        ScreenTriangle::new(positions.map(|position| ScreenVertex {
+            position,
+            depth: 0.5,
+            inv_w: 1.0,
+            varyings: (),
+            barycentrics: Vec3::ZERO,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_triangles_are_binned_into_every_tile_they_cover() {
+        let (width, height) = (200, 150);
+        let triangles = [
+            // Inside of a single tile
+            triangle([
+                Vec2::new(10.0, 10.0),
+                Vec2::new(50.0, 12.0),
+                Vec2::new(20.0, 40.0),
+            ]),
+            // Across the corner where four tiles meet
+            triangle([
+                Vec2::new(40.0, 30.0),
+                Vec2::new(100.0, 70.0),
+                Vec2::new(50.0, 110.0),
+            ]),
+            // Long and thin, across a row of tiles and into the partial one at the edge
+            triangle([
+                Vec2::new(5.0, 80.0),
+                Vec2::new(199.0, 100.0),
+                Vec2::new(5.0, 84.0),
+            ]),
+        ];
+        let bins = TileBins::new(width, height, &triangles);
+
+        for (i, triangle) in triangles.iter().enumerate() {
+            let mut tiles = Vec::new();
+            for y in 0..height {
+                for x in 0..width {
+                    let center = Vec2::new(x as f32 + 0.5, y as f32 + 0.5);
+                    if triangle.barycentrics(center).min_element() >= 0.0 {
+                        let tile = (x / TILE_SIZE, y / TILE_SIZE);
+                        assert!(
+                            bins.get(tile.0, tile.1).contains(&(i as u32)),
+                            "triangle {i} covers pixel ({x}, {y}), but isn't in tile {tile:?}"
+                        );
+                        if !tiles.contains(&tile) {
+                            tiles.push(tile);
+                        }
+                    }
+                }
+            }
+            assert!(!tiles.is_empty());
+        }
+
+        // The single tile triangle doesn't end up anywhere else
+        assert_eq!(bins.get(0, 0), [0, 1]);
+        for (tile_x, tile_y) in [(1, 0), (2, 0), (3, 0), (2, 1), (0, 2)] {
+            assert!(!bins.get(tile_x, tile_y).contains(&0));
+        }
+        assert!(bins.get(3, 1).contains(&2));
+    }
+}
@@ -0,0 +1,78 @@
+use std::{num::NonZeroU32, ops::Range, time::Duration};
+
+use crate::estimate::PixelEstimate;
+
+// Settings for progressive rendering: every pass adds a batch of samples to the pixels that are
+// still too noisy, until all of them are below the error target or the time runs out
+#[derive(Debug, Clone, Copy)]
+pub struct AdaptiveSampling {
+    // Samples per pixel per pass, at least 2 to be able to estimate the noise
+    pub batch_size: NonZeroU32,
+    // Relative standard error of a pixel's brightness at which it counts as converged
+    pub max_error: f32,
+    // Pixels stop getting samples at this count, even if they're still noisy
+    pub max_samples: NonZeroU32,
+    // Stops refining after this long, whatever the error. The last pass is cut short, but every
+    // pixel gets at least its first batch.
+    pub time_budget: Option<Duration>,
+}
+
+impl Default for AdaptiveSampling {
+    fn default() -> Self {
+        Self {
+            batch_size: NonZeroU32::new(16).unwrap(),
+            max_error: 0.01,
+            max_samples: NonZeroU32::new(4096).unwrap(),
+            time_budget: None,
+        }
+    }
+}
+
+impl AdaptiveSampling {
+    // The indices of the samples that a pixel gets in the next pass, after taking `taken` samples
+    // so far, or None if it's done. Samples that the filter gives no weight still count towards
+    // max_samples, and the last batch is cut short so the total doesn't go over it.
+    pub(crate) fn next_batch(&self, estimate: &PixelEstimate, taken: u32) -> Option<Range<u32>> {
+        let max_samples = self.max_samples.get();
+        if taken >= max_samples || estimate.relative_error() <= self.max_error {
+            return None;
+        }
+        Some(taken..(taken + self.batch_size.get()).min(max_samples))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+
+    use super::*;
+
+    #[test]
+    fn test_batches_stop_at_max_samples() {
+        let adaptive = AdaptiveSampling {
+            batch_size: NonZeroU32::new(16).unwrap(),
+            max_samples: NonZeroU32::new(40).unwrap(),
+            ..Default::default()
+        };
+        // Too few samples to tell the error, so it never converges
+        let estimate = PixelEstimate::default();
+
+        let mut taken = 0;
+        let mut batches = Vec::new();
+        while let Some(batch) = adaptive.next_batch(&estimate, taken) {
+            taken = batch.end;
+            batches.push(batch);
+        }
+        assert_eq!(batches, [0..16, 16..32, 32..40]);
+    }
+
+    #[test]
+    fn test_converged_pixels_get_no_samples() {
+        let adaptive = AdaptiveSampling::default();
+        let mut estimate = PixelEstimate::default();
+        for _ in 0..16 {
+            estimate.add(Vec3::splat(0.5), 1.0);
+        }
+        assert_eq!(adaptive.next_batch(&estimate, 16), None);
+    }
+}
@@ -3,13 +3,19 @@ use std::ops::Add;
 use common::model::triangle::Triangle;
 use glam::Vec3;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 // 24 bytes
 pub struct BoundingBox {
     pub min: glam::Vec3, // 12 bytes
     pub max: glam::Vec3, // 12 bytes
 }
 impl BoundingBox {
+    // Contains nothing, and adding it to another box leaves that box unchanged
+    pub const EMPTY: BoundingBox = BoundingBox {
+        min: Vec3::INFINITY,
+        max: Vec3::NEG_INFINITY,
+    };
+
     pub fn intersect(&self, ray: &crate::ray::Ray) -> Option<f32> {
         // https://en.wikipedia.org/wiki/Slab_method
         let inv_dir = 1.0 / ray.direction();
@@ -20,10 +26,11 @@ impl BoundingBox {
         let t_close_slabs = t_min_slabs.min(t_max_slabs);
         let t_far_slabs = t_min_slabs.max(t_max_slabs);
 
-        let t_close = t_close_slabs.max_element();
-        let t_far = t_far_slabs.min_element();
+        // Clip the slabs to the ray's interval, so a ray that starts inside the box hits it at t_min
+        let t_close = t_close_slabs.max_element().max(ray.t_min());
+        let t_far = t_far_slabs.min_element().min(ray.t_max());
 
-        if t_close > 0.0 && t_close <= t_far {
+        if t_close <= t_far {
             Some(t_close)
         } else {
             None
@@ -59,17 +66,13 @@ impl From<Triangle> for BoundingBox {
 
 impl<'a, V: Into<&'a BoundingBox>> FromIterator<V> for BoundingBox {
     fn from_iter<T: IntoIterator<Item = V>>(iter: T) -> Self {
-        iter.into_iter().map(|v| v.into()).fold(
-            BoundingBox {
-                min: Vec3::INFINITY,
-                max: Vec3::NEG_INFINITY,
-            },
-            |mut acc, v| {
+        iter.into_iter()
+            .map(|v| v.into())
+            .fold(BoundingBox::EMPTY, |mut acc, v| {
                 acc.min = acc.min.min(v.min);
                 acc.max = acc.max.max(v.max);
                 acc
-            },
-        )
+            })
     }
 }
 
@@ -83,3 +86,28 @@ impl Add for BoundingBox {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray::Ray;
+
+    #[test]
+    fn test_intersect_within_interval() {
+        // The ray enters the box at t = 1.5 and leaves it at t = 2.5
+        let bounding_box = BoundingBox {
+            min: Vec3::splat(-0.5),
+            max: Vec3::splat(0.5),
+        };
+        let ray = Ray::new(Vec3::new(-2.0, 0.1, 0.2), Vec3::X);
+        let intersect = |t_min, t_max| bounding_box.intersect(&ray.with_interval(t_min, t_max));
+
+        assert_eq!(intersect(0.0, f32::INFINITY), Some(1.5));
+        assert_eq!(intersect(0.0, 1.499), None);
+        assert_eq!(intersect(0.0, 1.501), Some(1.5));
+        // Starting inside of the box hits it right away
+        assert_eq!(intersect(2.0, f32::INFINITY), Some(2.0));
+        assert_eq!(intersect(2.499, f32::INFINITY), Some(2.499));
+        assert_eq!(intersect(2.501, f32::INFINITY), None);
+    }
+}
@@ -1,99 +1,315 @@
 use core::f32;
-use std::num::NonZeroU32;
+use std::num::{NonZeroU32, NonZeroUsize};
 
-use common::model::triangle::Triangle;
+use common::parallel;
+use glam::Vec3;
 
-use super::{Bvh, BvhNode};
+use super::{Bvh, BvhNode, Primitive};
 use crate::bvh::{BvhNodeKind, bounding_box::BoundingBox};
 
+// What the builder needs to know about each primitive
 #[derive(Debug, Clone)]
 struct BvhPrimitive {
-    triangle: Triangle,
     bounding_box: BoundingBox,
+    centroid: Vec3,
+    index: u32, // Position in the builder's input
 }
 
-#[derive(Debug, Clone, Default)]
-pub struct BvhBuilder {
+// How the builder decides where to split nodes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BvhMethod {
+    // Tries split positions along every axis, and picks the one with the lowest cost
+    #[default]
+    Sah,
+    // Linear BVH (LBVH): sorts the primitives along a Morton curve through their centroids, and
+    // splits wherever the curve crosses to another octant. Builds much faster, traces slower.
+    Linear,
+}
+
+// Settings for building a BVH, which trade build time against how fast rays traverse it
+#[derive(Debug, Clone, Copy)]
+pub struct BvhSettings {
+    pub method: BvhMethod,
+    // Nodes with more primitives than this always get split
+    pub max_leaf_size: NonZeroU32,
+    // The Surface Area Heuristic (SAH) cost model, which only the SAH method uses: the expected
+    // cost of a ray that hits a node is the cost of traversing it, plus the cost of intersecting
+    // the primitives in each child, weighted by the chance that the ray also hits that child. That
+    // chance is the ratio of the child's surface area to the parent's. Only the ratio between the
+    // two costs matters.
+    pub traversal_cost: f32,
+    pub intersection_cost: f32,
+    // Split candidates are the boundaries between this many bins of equal width along the
+    // centroid bounds, rather than every primitive's position
+    pub bins: NonZeroU32,
+}
+
+impl Default for BvhSettings {
+    fn default() -> Self {
+        Self {
+            method: BvhMethod::default(),
+            max_leaf_size: NonZeroU32::new(4).unwrap(),
+            traversal_cost: 1.0,
+            intersection_cost: 1.0,
+            bins: NonZeroU32::new(16).unwrap(),
+        }
+    }
+}
+
+impl BvhSettings {
+    fn leaf_cost(&self, num_primitives: usize) -> f32 {
+        self.intersection_cost * num_primitives as f32
+    }
+}
+
+// Nodes with fewer primitives than this are built on the thread that split them off, since starting
+// a new thread would take longer than building them
+const PARALLEL_THRESHOLD: usize = 1024;
+
+#[derive(Debug, Clone)]
+pub struct BvhBuilder<P> {
+    input: Vec<P>,
     primitives: Vec<BvhPrimitive>,
+    settings: BvhSettings,
+    threads: NonZeroUsize,
 }
 
-impl BvhBuilder {
-    pub fn new<I: Iterator<Item = Triangle>>(triangles: I) -> Self {
-        // Ideas: sort the triangles/bounding boxes along a space filling curve to see if that results in better cache locality while building the BVH
-        let primitives = triangles
-            .map(|t| BvhPrimitive {
-                bounding_box: BoundingBox::from(&t),
-                triangle: t,
+impl<P: Primitive> BvhBuilder<P> {
+    pub fn new<I: Iterator<Item = P>>(input: I) -> Self {
+        let input: Vec<P> = input.collect();
+        let primitives = input
+            .iter()
+            .enumerate()
+            .map(|(i, p)| {
+                let bounding_box = p.bounding_box();
+                BvhPrimitive {
+                    bounding_box,
+                    centroid: (bounding_box.min + bounding_box.max) / 2.0,
+                    index: i as u32,
+                }
             })
             .collect();
 
-        Self { primitives }
+        Self {
+            input,
+            primitives,
+            settings: BvhSettings::default(),
+            threads: parallel::default_threads(),
+        }
+    }
+
+    pub fn with_settings(mut self, settings: BvhSettings) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    // The result is the same for any number of threads
+    pub fn with_threads(mut self, threads: NonZeroUsize) -> Self {
+        self.threads = threads;
+        self
     }
 
-    pub fn build(&self) -> Bvh {
-        let indices = (0..self.primitives.len()).collect();
-        let root = self.build_node(indices);
+    pub fn build(&self) -> Bvh<P> {
+        let (nodes, indices) = self.build_nodes();
+        Bvh {
+            nodes,
+            primitives: indices
+                .iter()
+                .map(|&i| self.input[i as usize].clone())
+                .collect(),
+        }
+    }
+
+    // The flattened nodes, and for every primitive in their leaves, its position in the input
+    fn build_nodes(&self) -> (Vec<BvhNode>, Vec<u32>) {
+        // Nothing to build a tree over, and every node needs at least one primitive
+        if self.primitives.is_empty() {
+            return (Vec::new(), Vec::new());
+        }
+
+        let mut indices: Vec<usize> = (0..self.primitives.len()).collect();
+        let root = match self.settings.method {
+            BvhMethod::Sah => self.build_node(&mut indices, self.threads.get()),
+            BvhMethod::Linear => {
+                let codes = self.sort_by_morton_code(&mut indices);
+                self.build_linear_node(&codes, &indices, self.threads.get())
+            }
+        };
 
         // Now that we've made our splits, optimize the layout of the BVH for actual rendering
-        let mut triangles: Vec<Triangle> = Vec::with_capacity(self.primitives.len());
+        let mut indices: Vec<u32> = Vec::with_capacity(self.primitives.len());
         let mut nodes: Vec<BvhNode> = Vec::with_capacity(root.size());
 
-        root.flatten(&mut triangles, &mut nodes);
+        root.flatten(&mut indices, &mut nodes);
+        (nodes, indices)
+    }
 
-        Bvh { nodes, triangles }
+    // Builds the subtree over indices, which it reorders to partition the primitives between the
+    // children. Subtrees get built in parallel until each of the threads has one.
+    fn build_node<'a>(&'a self, indices: &mut [usize], threads: usize) -> BvhBuilderNode<'a> {
+        let split = if indices.len() > 1 {
+            best_split(&self.primitives, indices, &self.settings)
+        } else {
+            None
+        };
+
+        // Small enough nodes become leaves when no split is cheaper than intersecting all of their
+        // primitives, bigger ones get split no matter the cost
+        let fits_in_leaf = indices.len() <= self.settings.max_leaf_size.get() as usize;
+        let make_leaf = match &split {
+            Some(split) => fits_in_leaf && split.cost >= self.settings.leaf_cost(indices.len()),
+            None => fits_in_leaf,
+        };
+        if make_leaf {
+            return self.build_leaf(indices);
+        }
+
+        let mid = match split {
+            Some(split) => partition(indices, |i| split.goes_left(&self.primitives[i])),
+            // The centroids are all in the same spot, so no split is any better than another
+            None => indices.len() / 2,
+        };
+        let parallel = threads > 1 && indices.len() >= PARALLEL_THRESHOLD;
+        let (left_indices, right_indices) = indices.split_at_mut(mid);
+        build_internal(
+            parallel.then_some(threads),
+            |threads| self.build_node(left_indices, threads),
+            |threads| self.build_node(right_indices, threads),
+        )
     }
 
-    fn build_node<'a>(&'a self, indices: Vec<usize>) -> BvhBuilderNode<'a> {
-        if indices.len() == 1 {
-            let child = indices
-                .first()
-                .copied()
-                .map(|i| &self.primitives[i])
-                .unwrap();
-            BvhBuilderNode {
-                bounding_box: child.bounding_box,
-                kind: BvhBuilderNodeKind::Leaf {
-                    first_triangle: &child.triangle,
-                    second_triangle: None,
-                },
-            }
-        } else if indices.len() == 2 {
-            let first = indices
-                .get(0)
-                .copied()
-                .map(|i| &self.primitives[i])
-                .unwrap(); // We should never get an empty indices list.
-            let second = indices
-                .get(1)
-                .copied()
-                .map(|i| &self.primitives[i])
-                .unwrap();
-            let bounding_box = first.bounding_box + second.bounding_box;
-
-            BvhBuilderNode {
-                bounding_box,
-                kind: BvhBuilderNodeKind::Leaf {
-                    first_triangle: &first.triangle,
-                    second_triangle: Some(&second.triangle),
-                },
-            }
+    // Sorts indices along the Morton curve, and returns the sorted Morton codes
+    fn sort_by_morton_code(&self, indices: &mut [usize]) -> Vec<u64> {
+        let centroid_bounds =
+            self.primitives
+                .iter()
+                .fold(BoundingBox::EMPTY, |acc, p| BoundingBox {
+                    min: acc.min.min(p.centroid),
+                    max: acc.max.max(p.centroid),
+                });
+        let extent = centroid_bounds.max - centroid_bounds.min;
+
+        let mut keyed: Vec<(u64, usize)> = indices
+            .iter()
+            .map(|&i| {
+                let normalized = (self.primitives[i].centroid - centroid_bounds.min) / extent;
+                // Flat axes divide by zero, all of their centroids are at the start
+                let normalized = Vec3::select(extent.cmpgt(Vec3::ZERO), normalized, Vec3::ZERO);
+                (morton_code(normalized), i)
+            })
+            .collect();
+        // Ties are broken by index, so the order doesn't depend on the sort
+        keyed.sort_unstable();
+
+        for (index, &(_, i)) in indices.iter_mut().zip(&keyed) {
+            *index = i;
+        }
+        keyed.into_iter().map(|(code, _)| code).collect()
+    }
+
+    // Builds the subtree over the primitives in indices, which are sorted by their Morton codes.
+    // The primitives whose codes have the highest differing bit set go to the right child.
+    fn build_linear_node<'a>(
+        &'a self,
+        codes: &[u64],
+        indices: &[usize],
+        threads: usize,
+    ) -> BvhBuilderNode<'a> {
+        if indices.len() <= self.settings.max_leaf_size.get() as usize {
+            return self.build_leaf(indices);
+        }
+
+        let first = codes[0];
+        let last = codes[codes.len() - 1];
+        let mid = if first == last {
+            // Centroids in the same cell can't be told apart
+            codes.len() / 2
         } else {
-            let (left_indices, right_indices) = split_along_optimal_axis(&self.primitives, indices);
-            let left_child = Box::new(self.build_node(left_indices));
-            let right_child = Box::new(self.build_node(right_indices));
-
-            let bounding_box = left_child.bounding_box + right_child.bounding_box;
-            BvhBuilderNode {
-                bounding_box,
-                kind: BvhBuilderNodeKind::Internal {
-                    first_child: left_child,
-                    second_child: right_child,
-                },
-            }
+            // The codes are sorted, so all of them share the bits above this one, and this one
+            // switches from 0 to 1 exactly once
+            let bit = 1 << (63 - (first ^ last).leading_zeros());
+            codes.partition_point(|code| code & bit == 0)
+        };
+
+        let parallel = threads > 1 && indices.len() >= PARALLEL_THRESHOLD;
+        build_internal(
+            parallel.then_some(threads),
+            |threads| self.build_linear_node(&codes[..mid], &indices[..mid], threads),
+            |threads| self.build_linear_node(&codes[mid..], &indices[mid..], threads),
+        )
+    }
+
+    fn build_leaf<'a>(&'a self, indices: &[usize]) -> BvhBuilderNode<'a> {
+        let primitives: Vec<&BvhPrimitive> = indices.iter().map(|&i| &self.primitives[i]).collect();
+        let bounding_box = BoundingBox::from_iter(primitives.iter().map(|p| &p.bounding_box));
+
+        BvhBuilderNode {
+            bounding_box,
+            kind: BvhBuilderNodeKind::Leaf { primitives },
         }
     }
 }
 
+// An internal node over the two subtrees that left and right build. With Some(threads), they're
+// built in parallel, and split the threads between them.
+fn build_internal<'a>(
+    threads: Option<usize>,
+    left: impl FnOnce(usize) -> BvhBuilderNode<'a> + Send,
+    right: impl FnOnce(usize) -> BvhBuilderNode<'a> + Send,
+) -> BvhBuilderNode<'a> {
+    let (left_child, right_child) = match threads {
+        Some(threads) => {
+            let left_threads = threads / 2;
+            parallel::join(|| left(left_threads), || right(threads - left_threads))
+        }
+        None => (left(1), right(1)),
+    };
+
+    BvhBuilderNode {
+        bounding_box: left_child.bounding_box + right_child.bounding_box,
+        kind: BvhBuilderNodeKind::Internal {
+            first_child: Box::new(left_child),
+            second_child: Box::new(right_child),
+        },
+    }
+}
+
+// Interleaves 21 bits of each coordinate of a point in the unit cube into a 63 bit Morton code
+fn morton_code(point: Vec3) -> u64 {
+    const SCALE: f32 = (1 << 21) as f32;
+    let quantize = |x: f32| ((x * SCALE) as u64).min((1 << 21) - 1);
+    (spread_bits(quantize(point.x)) << 2)
+        | (spread_bits(quantize(point.y)) << 1)
+        | spread_bits(quantize(point.z))
+}
+
+// Moves the lowest 21 bits of x two bits apart from each other
+fn spread_bits(x: u64) -> u64 {
+    let mut x = x & 0x1f_ffff;
+    x = (x | x << 32) & 0x001f_0000_0000_ffff;
+    x = (x | x << 16) & 0x001f_0000_ff00_00ff;
+    x = (x | x << 8) & 0x100f_00f0_0f00_f00f;
+    x = (x | x << 4) & 0x10c3_0c30_c30c_30c3;
+    x = (x | x << 2) & 0x1249_2492_4924_9249;
+    x
+}
+
+// Moves the indices that go left to the front, and returns how many of them there are. Unlike
+// Iterator::partition, this doesn't allocate, but it also doesn't keep the order.
+fn partition(indices: &mut [usize], goes_left: impl Fn(usize) -> bool) -> usize {
+    let mut left = 0;
+    let mut right = indices.len();
+    while left < right {
+        if goes_left(indices[left]) {
+            left += 1;
+        } else {
+            right -= 1;
+            indices.swap(left, right);
+        }
+    }
+    left
+}
+
 // Nodes
 struct BvhBuilderNode<'a> {
     bounding_box: BoundingBox,
@@ -102,8 +318,7 @@ struct BvhBuilderNode<'a> {
 
 enum BvhBuilderNodeKind<'a> {
     Leaf {
-        first_triangle: &'a Triangle,
-        second_triangle: Option<&'a Triangle>,
+        primitives: Vec<&'a BvhPrimitive>, // Never empty
     },
     Internal {
         first_child: Box<BvhBuilderNode<'a>>,
@@ -115,7 +330,7 @@ impl<'a> BvhBuilderNode<'a> {
     pub fn size(&self) -> usize {
         // 1 (for itself) + size of each of the children
         let size_children = match &self.kind {
-            BvhBuilderNodeKind::Leaf { .. } => 0, // triangles don't count as an additional node, they "belong" to the leaf node
+            BvhBuilderNodeKind::Leaf { .. } => 0, // primitives don't count as an additional node, they "belong" to the leaf node
             BvhBuilderNodeKind::Internal {
                 first_child,
                 second_child,
@@ -125,36 +340,17 @@ impl<'a> BvhBuilderNode<'a> {
         1 + size_children
     }
 
-    pub fn flatten(self, triangles: &mut Vec<Triangle>, nodes: &mut Vec<BvhNode>) {
+    pub fn flatten(self, indices: &mut Vec<u32>, nodes: &mut Vec<BvhNode>) {
         match self.kind {
-            // if this is a leaf node, add the triangles to the triangle vector + put a Leaf node on the nodes array
-            BvhBuilderNodeKind::Leaf {
-                first_triangle,
-                second_triangle: Some(second_triangle),
-            } => {
-                let triangle_offset = triangles.len();
-                triangles.push(*first_triangle);
-                triangles.push(*second_triangle);
-                let node = BvhNode {
-                    bounding_box: self.bounding_box,
-                    kind: BvhNodeKind::Leaf {
-                        triangle_offset: triangle_offset as u32,
-                        num_triangles: NonZeroU32::new(2).unwrap(),
-                    },
-                };
-                nodes.push(node);
-            }
-            BvhBuilderNodeKind::Leaf {
-                first_triangle,
-                second_triangle: None,
-            } => {
-                let triangle_offset = triangles.len();
-                triangles.push(*first_triangle);
+            // if this is a leaf node, add the primitives to the indices vector + put a Leaf node on the nodes array
+            BvhBuilderNodeKind::Leaf { primitives } => {
+                let primitive_offset = indices.len();
+                indices.extend(primitives.iter().map(|primitive| primitive.index));
                 let node = BvhNode {
                     bounding_box: self.bounding_box,
                     kind: BvhNodeKind::Leaf {
-                        triangle_offset: triangle_offset as u32,
-                        num_triangles: NonZeroU32::new(1).unwrap(),
+                        primitive_offset: primitive_offset as u32,
+                        num_primitives: NonZeroU32::new(primitives.len() as u32).unwrap(),
                     },
                 };
                 nodes.push(node);
@@ -171,10 +367,10 @@ impl<'a> BvhBuilderNode<'a> {
                     },
                     bounding_box: self.bounding_box,
                 });
-                first_child.flatten(triangles, nodes);
+                first_child.flatten(indices, nodes);
                 // get the index of the 2nd child
                 let right_index = nodes.len();
-                second_child.flatten(triangles, nodes);
+                second_child.flatten(indices, nodes);
 
                 if let BvhNodeKind::Internal { right_offset, .. } = &mut nodes[node_index].kind {
                     *right_offset = right_index as u32 // Set the offset now that we've constructed the children
@@ -186,45 +382,333 @@ impl<'a> BvhBuilderNode<'a> {
     }
 }
 
-// Find the optimal splitting axis + split along that axis
-fn split_along_optimal_axis(
+#[derive(Debug, Clone, Copy)]
+struct Bin {
+    bounding_box: BoundingBox,
+    count: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Split {
+    axis: usize,
+    bin: usize, // The first bin on the right side
+    min: f32,
+    scale: f32,
+    bins: usize,
+    cost: f32,
+}
+
+impl Split {
+    fn goes_left(&self, primitive: &BvhPrimitive) -> bool {
+        bin_index(
+            primitive.centroid[self.axis],
+            self.min,
+            self.scale,
+            self.bins,
+        ) < self.bin
+    }
+}
+
+fn bin_index(centroid: f32, min: f32, scale: f32, bins: usize) -> usize {
+    (((centroid - min) * scale) as usize).min(bins - 1)
+}
+
+// The cheapest split over all axes, or None when the centroids don't spread out along any of them
+// or the bounding box has no area
+fn best_split(
     primitives: &[BvhPrimitive],
-    indices: Vec<usize>,
-) -> (Vec<usize>, Vec<usize>) {
-    let mut best_score = f32::INFINITY;
-    let mut best_indices = None;
+    indices: &[usize],
+    settings: &BvhSettings,
+) -> Option<Split> {
+    let bounding_box = BoundingBox::from_iter(indices.iter().map(|&i| &primitives[i].bounding_box));
+    let centroid_bounds = indices.iter().map(|&i| primitives[i].centroid).fold(
+        BoundingBox::EMPTY,
+        |acc, centroid| BoundingBox {
+            min: acc.min.min(centroid),
+            max: acc.max.max(centroid),
+        },
+    );
+    let area = bounding_box.area();
+    // Flat or degenerate primitives leave nothing to weigh the children's areas against
+    if area <= 0.0 {
+        return None;
+    }
+    let num_bins = settings.bins.get() as usize;
+    let empty_bin = Bin {
+        bounding_box: BoundingBox::EMPTY,
+        count: 0,
+    };
+    let mut bins = vec![empty_bin; num_bins];
+    let mut right_costs = vec![0.0; num_bins];
 
+    let mut best: Option<Split> = None;
     for axis in 0..=2 {
-        let (indices_left, indices_right) = split_along_axis(primitives, indices.clone(), axis);
-        let bounding_box_left =
-            BoundingBox::from_iter(indices_left.iter().map(|&i| &primitives[i].bounding_box));
-        let bounding_box_right =
-            BoundingBox::from_iter(indices_right.iter().map(|&i| &primitives[i].bounding_box));
-        let score = bounding_box_left.area() + bounding_box_right.area();
-        if score < best_score {
-            best_score = score;
-            best_indices = Some((indices_left, indices_right));
+        let min = centroid_bounds.min[axis];
+        let extent = centroid_bounds.max[axis] - min;
+        if extent <= 0.0 {
+            continue;
+        }
+        let scale = num_bins as f32 / extent;
+
+        bins.fill(empty_bin);
+        for &i in indices {
+            let primitive = &primitives[i];
+            let bin = &mut bins[bin_index(primitive.centroid[axis], min, scale, num_bins)];
+            bin.bounding_box = bin.bounding_box + primitive.bounding_box;
+            bin.count += 1;
+        }
+
+        // Sweep from the right first, so the sweep from the left can score every split at once
+        let mut right = empty_bin;
+        for bin in (1..num_bins).rev() {
+            right.bounding_box = right.bounding_box + bins[bin].bounding_box;
+            right.count += bins[bin].count;
+            right_costs[bin] = right.bounding_box.area() * right.count as f32;
+        }
+
+        let mut left = empty_bin;
+        for bin in 1..num_bins {
+            left.bounding_box = left.bounding_box + bins[bin - 1].bounding_box;
+            left.count += bins[bin - 1].count;
+            if left.count == 0 || left.count == indices.len() {
+                continue;
+            }
+
+            let cost = settings.traversal_cost
+                + settings.intersection_cost
+                    * (left.bounding_box.area() * left.count as f32 + right_costs[bin])
+                    / area;
+            if best.is_none_or(|best| cost < best.cost) {
+                best = Some(Split {
+                    axis,
+                    bin,
+                    min,
+                    scale,
+                    bins: num_bins,
+                    cost,
+                });
+            }
         }
     }
 
-    best_indices.unwrap()
+    best
 }
 
-fn split_along_axis(
-    primitives: &[BvhPrimitive],
-    mut indices: Vec<usize>,
-    axis: usize,
-) -> (Vec<usize>, Vec<usize>) {
-    assert!(axis <= 2, "Axis needs to be 0, 1, or 2.");
-
-    indices.sort_by(|&a, &b| {
-        primitives[a].bounding_box.min[axis]
-            .partial_cmp(&primitives[b].bounding_box.min[axis])
-            .unwrap()
-    });
-
-    // Split that shit down the middle
-    let mid = indices.len() / 2;
-    let (left, right) = indices.split_at(mid);
-    (left.to_vec(), right.to_vec())
+#[cfg(test)]
+mod tests {
+    use common::model::triangle::{Triangle, Vertex};
+
+    use super::*;
+    use crate::{intersect::Intersect, ray::Ray, sampling::Rng, test_scenes::scene_triangles};
+
+    const METHODS: [BvhMethod; 2] = [BvhMethod::Sah, BvhMethod::Linear];
+
+    // Builds the tree that splits every node at the median centroid along its longest axis
+    fn build_median_node<'a>(
+        builder: &'a BvhBuilder<Triangle>,
+        indices: &mut [usize],
+    ) -> BvhBuilderNode<'a> {
+        if indices.len() <= builder.settings.max_leaf_size.get() as usize {
+            return builder.build_leaf(indices);
+        }
+
+        let centroids = indices
+            .iter()
+            .map(|&i| builder.primitives[i].centroid)
+            .fold(BoundingBox::EMPTY, |acc, centroid| BoundingBox {
+                min: acc.min.min(centroid),
+                max: acc.max.max(centroid),
+            });
+        let axis = (centroids.max - centroids.min).max_position();
+        indices.sort_by(|&a, &b| {
+            let centroid = |i: usize| builder.primitives[i].centroid[axis];
+            centroid(a).total_cmp(&centroid(b))
+        });
+
+        let (left, right) = indices.split_at_mut(indices.len() / 2);
+        build_internal(
+            None,
+            |_| build_median_node(builder, left),
+            |_| build_median_node(builder, right),
+        )
+    }
+
+    // The expected cost of a ray that hits the root, according to the SAH cost model
+    fn sah_cost(node: &BvhBuilderNode, settings: &BvhSettings) -> f32 {
+        match &node.kind {
+            BvhBuilderNodeKind::Leaf { primitives } => {
+                node.bounding_box.area() * settings.leaf_cost(primitives.len())
+            }
+            BvhBuilderNodeKind::Internal {
+                first_child,
+                second_child,
+            } => {
+                node.bounding_box.area() * settings.traversal_cost
+                    + sah_cost(first_child, settings)
+                    + sah_cost(second_child, settings)
+            }
+        }
+    }
+
+    #[test]
+    fn test_sah_is_cheaper_than_median_split() {
+        let builder = BvhBuilder::new(scene_triangles("teapot").into_iter());
+        let indices = || (0..builder.primitives.len()).collect::<Vec<_>>();
+        let sah = builder.build_node(&mut indices(), 1);
+        let median = build_median_node(&builder, &mut indices());
+
+        let sah = sah_cost(&sah, &builder.settings);
+        let median = sah_cost(&median, &builder.settings);
+        assert!(sah < median, "{sah} >= {median}");
+    }
+
+    #[test]
+    fn test_no_split_without_area() {
+        // Points along the x axis, whose bounding box is a line
+        let triangles = (0..8).map(|i| {
+            let vertex = Vertex::new(Vec3::new(i as f32, 0.0, 0.0), Vec3::Y, None);
+            Triangle {
+                v1: vertex,
+                v2: vertex,
+                v3: vertex,
+            }
+        });
+        let builder = BvhBuilder::new(triangles);
+        let indices: Vec<usize> = (0..builder.primitives.len()).collect();
+        assert!(best_split(&builder.primitives, &indices, &builder.settings).is_none());
+    }
+
+    #[test]
+    fn test_leaves_respect_max_leaf_size() {
+        let triangles = scene_triangles("teapot");
+        for (method, max_leaf_size) in METHODS.into_iter().flat_map(|m| [(m, 1), (m, 2), (m, 7)]) {
+            let (nodes, mut indices) = BvhBuilder::new(triangles.iter().copied())
+                .with_settings(BvhSettings {
+                    method,
+                    max_leaf_size: NonZeroU32::new(max_leaf_size).unwrap(),
+                    ..Default::default()
+                })
+                .build_nodes();
+
+            for node in &nodes {
+                if let BvhNodeKind::Leaf { num_primitives, .. } = node.kind {
+                    assert!(num_primitives.get() <= max_leaf_size);
+                }
+            }
+
+            // Every triangle ends up in exactly one leaf
+            indices.sort();
+            assert!(indices.into_iter().eq(0..triangles.len() as u32));
+        }
+    }
+
+    #[test]
+    fn test_parallel_build_matches_serial() {
+        let triangles = scene_triangles("teapot");
+        for method in METHODS {
+            let build = |threads| {
+                BvhBuilder::new(triangles.iter().copied())
+                    .with_settings(BvhSettings {
+                        method,
+                        ..Default::default()
+                    })
+                    .with_threads(NonZeroUsize::new(threads).unwrap())
+                    .build_nodes()
+            };
+
+            let serial = build(1);
+            for threads in [2, 3, 8] {
+                assert_eq!(serial, build(threads));
+            }
+        }
+    }
+
+    #[test]
+    fn test_methods_find_the_closest_hit() {
+        let triangles = scene_triangles("teapot");
+        for method in METHODS {
+            let bvh = BvhBuilder::new(triangles.iter().copied())
+                .with_settings(BvhSettings {
+                    method,
+                    ..Default::default()
+                })
+                .build();
+
+            // Rays from all around the teapot, towards its middle
+            for i in 0..200 {
+                let angle = i as f32 * 0.1;
+                let origin = Vec3::new(
+                    angle.cos() * 200.0,
+                    (i % 9) as f32 * 10.0,
+                    angle.sin() * 200.0,
+                );
+                let ray = Ray::new(origin, Vec3::new(0.0, 40.0, 0.0) - origin);
+
+                let closest = triangles
+                    .iter()
+                    .filter_map(|triangle| triangle.intersect(&ray))
+                    .map(|hit| hit.t)
+                    .min_by(f32::total_cmp);
+                assert_eq!(bvh.intersect(&ray).map(|hit| hit.t), closest);
+            }
+        }
+    }
+
+    #[test]
+    fn test_occluded_matches_intersect() {
+        let triangles = scene_triangles("teapot");
+        let mut rng = Rng::new(7, 0);
+        let mut next_vec3 = || Vec3::new(rng.next_f32(), rng.next_f32(), rng.next_f32());
+
+        for method in METHODS {
+            let bvh = BvhBuilder::new(triangles.iter().copied())
+                .with_settings(BvhSettings {
+                    method,
+                    ..Default::default()
+                })
+                .build();
+
+            let mut occluded = 0;
+            for _ in 0..2000 {
+                // From anywhere in and around the teapot, in any direction, for any distance
+                let origin =
+                    Vec3::new(-100.0, -20.0, -70.0) + next_vec3() * Vec3::new(200.0, 120.0, 140.0);
+                let direction = next_vec3() * 2.0 - 1.0;
+                let t_max = next_vec3().x * 150.0;
+                let ray = Ray::new(origin, direction);
+
+                let expected = bvh.intersect(&ray).is_some_and(|hit| hit.t < t_max);
+                assert_eq!(
+                    bvh.occluded(&ray.with_interval(0.0, t_max)),
+                    expected,
+                    "{ray:?} up to {t_max}"
+                );
+                occluded += expected as u32;
+            }
+            // Both outcomes happen often enough for the comparison to mean something
+            assert!((100..1900).contains(&occluded), "{occluded}");
+        }
+    }
+
+    #[test]
+    fn test_bvh_hits_only_within_interval() {
+        let bvh = BvhBuilder::new(scene_triangles("cube").into_iter()).build();
+
+        // Hits the +x face at t = 1.5 and the -x face at t = 2.5
+        let ray = Ray::new(Vec3::new(2.0, 0.1, 0.2), Vec3::NEG_X);
+        for (t_min, t_max, expected) in [
+            (0.0, f32::INFINITY, Some(1.5)),
+            (0.0, 1.499, None),
+            (0.0, 1.501, Some(1.5)),
+            (1.501, f32::INFINITY, Some(2.5)),
+            (2.501, f32::INFINITY, None),
+        ] {
+            let ray = ray.with_interval(t_min, t_max);
+            let t = bvh
+                .intersect(&ray)
+                .map(|hit| (hit.t * 1000.0).round() / 1000.0);
+            assert_eq!(t, expected, "{t_min}..{t_max}");
+            assert_eq!(bvh.occluded(&ray), expected.is_some(), "{t_min}..{t_max}");
+        }
+    }
 }
@@ -0,0 +1,107 @@
+use std::sync::Arc;
+
+use glam::{Affine3A, Mat3A, Vec3};
+
+use super::{Bvh, Primitive, bounding_box::BoundingBox};
+use crate::{
+    intersect::{Intersect, Intersection},
+    ray::Ray,
+};
+
+// A bottom level BVH over the triangles of a mesh, placed in the world. The top level BVH is built
+// over these, and any number of them can share the same bottom level BVH.
+#[derive(Clone)]
+pub struct BvhInstance {
+    pub mesh: usize, // Index into the scene's meshes
+    blas: Arc<Bvh>,
+    // None for the identity, which is most instances, and doesn't need rays or hits transformed
+    transform: Option<Transform>,
+    bounding_box: BoundingBox,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Transform {
+    object_to_world: Affine3A,
+    world_to_object: Affine3A,
+    // Transforms normals, keeping them perpendicular to the surface under non-uniform scaling
+    normal_matrix: Mat3A,
+}
+
+impl BvhInstance {
+    pub fn new(mesh: usize, blas: Arc<Bvh>, transform: Affine3A) -> Self {
+        let local = blas.bounding_box();
+        let (transform, bounding_box) = if transform == Affine3A::IDENTITY {
+            (None, local)
+        } else {
+            // The box around the transformed corners of the bottom level BVH's box
+            let corners = (0..8).map(|i| {
+                let corner = Vec3::new(
+                    if i & 1 == 0 { local.min.x } else { local.max.x },
+                    if i & 2 == 0 { local.min.y } else { local.max.y },
+                    if i & 4 == 0 { local.min.z } else { local.max.z },
+                );
+                let corner = transform.transform_point3(corner);
+                BoundingBox {
+                    min: corner,
+                    max: corner,
+                }
+            });
+            let transform = Transform {
+                object_to_world: transform,
+                world_to_object: transform.inverse(),
+                normal_matrix: transform.matrix3.inverse().transpose(),
+            };
+            (Some(transform), corners.reduce(|a, b| a + b).unwrap())
+        };
+
+        Self {
+            mesh,
+            blas,
+            transform,
+            bounding_box,
+        }
+    }
+
+    pub fn blas(&self) -> &Bvh {
+        &self.blas
+    }
+
+    // The ray in the space of the mesh, where the bottom level BVH is
+    pub fn to_object_space(&self, ray: &Ray) -> Ray {
+        match &self.transform {
+            Some(transform) => ray.transformed(&transform.world_to_object),
+            None => *ray,
+        }
+    }
+}
+
+impl Intersect for BvhInstance {
+    fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+        let hit = self.blas.intersect(&self.to_object_space(ray))?;
+        let Some(transform) = &self.transform else {
+            return Some(hit);
+        };
+
+        // Distances along the ray are the same in both spaces, so t doesn't change
+        Some(Intersection {
+            point: transform.object_to_world.transform_point3(hit.point),
+            normal: (transform.normal_matrix * hit.normal).normalize_or_zero(),
+            geometric_normal: (transform.normal_matrix * hit.geometric_normal).normalize_or_zero(),
+            ..hit
+        })
+    }
+}
+
+impl Primitive for BvhInstance {
+    fn bounding_box(&self) -> BoundingBox {
+        self.bounding_box
+    }
+
+    fn record_hit(intersection: &mut Intersection, index: u32) {
+        intersection.instance = index;
+    }
+
+    fn occluded(&self, ray: &Ray) -> bool {
+        self.blas.occluded(&self.to_object_space(ray))
+    }
+}
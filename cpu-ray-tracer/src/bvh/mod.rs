@@ -2,60 +2,110 @@ use core::f32;
 use std::{cell::RefCell, num::NonZero};
 
 use common::model::triangle::Triangle;
-use glam::{Vec2, Vec3};
 
 use crate::{
     bvh::bounding_box::BoundingBox,
     intersect::{Intersect, Intersection},
+    ray::Ray,
 };
 
 mod bounding_box;
 pub mod builder;
+pub mod instance;
 
-pub struct Bvh {
+// Anything that a BVH can be built over
+pub trait Primitive: Intersect + Clone + Send + Sync {
+    fn bounding_box(&self) -> BoundingBox;
+
+    // Records in a hit which of the BVH's primitives it's on
+    fn record_hit(intersection: &mut Intersection, index: u32);
+
+    // Whether the ray hits the primitive at all, which doesn't need the closest hit
+    fn occluded(&self, ray: &Ray) -> bool {
+        self.intersect(ray).is_some()
+    }
+}
+
+impl Primitive for Triangle {
+    fn bounding_box(&self) -> BoundingBox {
+        BoundingBox::from(self)
+    }
+
+    fn record_hit(intersection: &mut Intersection, index: u32) {
+        intersection.primitive = index;
+    }
+}
+
+pub struct Bvh<P = Triangle> {
     nodes: Vec<BvhNode>,
-    triangles: Vec<Triangle>,
+    primitives: Vec<P>,
 }
 
 // 32 bytes
+#[derive(Debug, PartialEq)]
 pub struct BvhNode {
     kind: BvhNodeKind,         // 8 bytes
     bounding_box: BoundingBox, // 24 bytes
 }
 
+#[derive(Debug, PartialEq)]
 enum BvhNodeKind {
     Internal {
         right_offset: u32,
     }, // 4 bytes
     Leaf {
-        triangle_offset: u32,
-        num_triangles: NonZero<u32>,
+        primitive_offset: u32,
+        num_primitives: NonZero<u32>,
     }, // 8 bytes
 }
 
 thread_local! {
-    static STACK: RefCell<Vec<(f32, u32)>> = RefCell::new(Vec::with_capacity(16));
+    // Spare traversal stacks. A top level BVH traverses bottom level ones from its leaves, so
+    // every traversal takes a stack of its own out of here while it runs.
+    static STACKS: RefCell<Vec<Vec<(f32, u32)>>> = const { RefCell::new(Vec::new()) };
+}
+
+fn with_stack<R>(f: impl FnOnce(&mut Vec<(f32, u32)>) -> R) -> R {
+    let mut stack = STACKS
+        .with_borrow_mut(|stacks| stacks.pop())
+        .unwrap_or_else(|| Vec::with_capacity(16));
+    let result = f(&mut stack);
+    STACKS.with_borrow_mut(|stacks| stacks.push(stack));
+    result
 }
 
-impl Bvh {
+impl<P: Primitive> Bvh<P> {
+    // The primitive that an intersection with this BVH refers to
+    pub fn primitive(&self, index: u32) -> &P {
+        &self.primitives[index as usize]
+    }
+
+    // Bounds everything in the BVH
+    pub fn bounding_box(&self) -> BoundingBox {
+        self.nodes
+            .first()
+            .map_or(BoundingBox::EMPTY, |root| root.bounding_box)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.primitives.is_empty()
+    }
+
     // TODO: figure out a way to make this non-allocating, instead of having to pass in a threadlocal stack
     fn intersect_loop(
         &self,
         stack: &mut Vec<(f32, u32)>,
         ray: &crate::ray::Ray,
     ) -> Option<Intersection> {
-        let mut closest_intersection: Intersection = Intersection {
-            t: f32::INFINITY,
-            point: Vec3::ZERO,
-            normal: Vec3::ZERO,
-            uv: Vec2::ZERO,
-        };
+        let mut closest_intersection: Option<Intersection> = None;
+        // Shrinks to the closest hit so far, so nodes and primitives behind it get skipped
+        let mut ray = *ray;
 
         // Intersect the root node
-        let mut next_item = Some((0.0, 0_u32));
+        let mut next_item = Some((ray.t_min(), 0_u32));
 
         while let Some((distance, node_index)) = next_item.take().or_else(|| stack.pop()) {
-            if distance > closest_intersection.t {
+            if distance > ray.t_max() {
                 continue;
             }
             let node = &self.nodes[node_index as usize];
@@ -67,8 +117,8 @@ impl Bvh {
                     let left = &self.nodes[left_offset as usize];
                     let right = &self.nodes[right_offset as usize];
 
-                    let left_distance = left.bounding_box.intersect(ray);
-                    let right_distance = right.bounding_box.intersect(ray);
+                    let left_distance = left.bounding_box.intersect(&ray);
+                    let right_distance = right.bounding_box.intersect(&ray);
 
                     match (left_distance, right_distance) {
                         (Some(left_t), Some(right_t)) => {
@@ -90,37 +140,90 @@ impl Bvh {
                     }
                 }
                 BvhNodeKind::Leaf {
-                    triangle_offset,
-                    num_triangles,
+                    primitive_offset,
+                    num_primitives,
                 } => {
-                    // Intersect with some triangles
-                    for i in
-                        triangle_offset as usize..(triangle_offset + num_triangles.get()) as usize
-                    {
-                        let triangle = &self.triangles[i];
-                        if let Some(intersection) = triangle.intersect(ray)
-                            && intersection.t < closest_intersection.t
-                        {
-                            closest_intersection = intersection;
+                    // Intersect with some primitives
+                    for i in primitive_offset..primitive_offset + num_primitives.get() {
+                        let primitive = &self.primitives[i as usize];
+                        if let Some(mut intersection) = primitive.intersect(&ray) {
+                            ray = ray.with_interval(ray.t_min(), intersection.t);
+                            P::record_hit(&mut intersection, i);
+                            closest_intersection = Some(intersection);
                         }
                     }
                 }
             }
         }
 
-        if closest_intersection.t.is_finite() {
-            Some(closest_intersection)
-        } else {
-            None
+        closest_intersection
+    }
+}
+
+impl<P: Primitive> Bvh<P> {
+    // Whether anything is hit within the ray's interval. Unlike intersect, this doesn't look for
+    // the closest hit, so it can stop at the first one, which is all shadow rays need.
+    pub fn occluded(&self, ray: &crate::ray::Ray) -> bool {
+        if self.is_empty() {
+            return false;
+        }
+        with_stack(|stack| self.occluded_loop(stack, ray))
+    }
+
+    fn occluded_loop(&self, stack: &mut Vec<(f32, u32)>, ray: &crate::ray::Ray) -> bool {
+        stack.clear();
+        let mut next_item = Some(0_u32);
+
+        while let Some(node_index) = next_item.take().or_else(|| stack.pop().map(|(_, i)| i)) {
+            let node = &self.nodes[node_index as usize];
+            match node.kind {
+                BvhNodeKind::Internal { right_offset } => {
+                    // The order doesn't matter, any hit will do
+                    let left_offset = node_index + 1;
+                    for child in [left_offset, right_offset] {
+                        if self.nodes[child as usize]
+                            .bounding_box
+                            .intersect(ray)
+                            .is_some()
+                        {
+                            if next_item.is_none() {
+                                next_item = Some(child);
+                            } else {
+                                stack.push((0.0, child));
+                            }
+                        }
+                    }
+                }
+                BvhNodeKind::Leaf {
+                    primitive_offset,
+                    num_primitives,
+                } => {
+                    let primitives = primitive_offset as usize
+                        ..(primitive_offset + num_primitives.get()) as usize;
+                    if self.primitives[primitives]
+                        .iter()
+                        .any(|primitive| primitive.occluded(ray))
+                    {
+                        // Leave the stack empty for the next query
+                        stack.clear();
+                        return true;
+                    }
+                }
+            }
         }
+
+        false
     }
 }
 
-impl Intersect for Bvh {
+impl<P: Primitive> Intersect for Bvh<P> {
     fn intersect(&self, ray: &crate::ray::Ray) -> Option<Intersection> {
         // let mut stack = Vec::with_capacity(16);
         // return self.intersect_loop(&mut stack, ray);
-        STACK.with_borrow_mut(|stack| self.intersect_loop(stack, ray))
+        if self.is_empty() {
+            return None;
+        }
+        with_stack(|stack| self.intersect_loop(stack, ray))
     }
 }
 
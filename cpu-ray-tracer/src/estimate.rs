@@ -0,0 +1,57 @@
+use glam::Vec3;
+
+// Pixels this dark count as this bright when computing the relative error, so that the noise in
+// nearly black pixels doesn't keep them from converging
+const MIN_LUMINANCE: f32 = 0.01;
+
+// The running estimate of a pixel's color from the samples so far
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PixelEstimate {
+    // Filter weighted sum of the samples
+    sum: Vec3,
+    weight_sum: f32,
+    // Same, but ignoring the sign of the weights
+    abs_sum: Vec3,
+    abs_weight_sum: f32,
+    // Running mean and variance of the samples' luminance, using Welford's algorithm
+    samples: u32,
+    mean: f32,
+    squared_deviations: f32,
+}
+
+impl PixelEstimate {
+    pub fn add(&mut self, radiance: Vec3, weight: f32) {
+        self.sum += weight * radiance;
+        self.weight_sum += weight;
+        self.abs_sum += weight.abs() * radiance;
+        self.abs_weight_sum += weight.abs();
+
+        let luminance = radiance.dot(Vec3::new(0.2126, 0.7152, 0.0722));
+        self.samples += 1;
+        let delta = luminance - self.mean;
+        self.mean += delta / self.samples as f32;
+        self.squared_deviations += delta * (luminance - self.mean);
+    }
+
+    pub fn color(&self) -> Vec3 {
+        // With few samples, the negative lobes of a filter can cancel out most of the weight,
+        // which blows up the noise. Fall back to ignoring the sign of the weights then.
+        if self.weight_sum > 0.1 * self.abs_weight_sum {
+            self.sum / self.weight_sum
+        } else if self.abs_weight_sum > 0.0 {
+            self.abs_sum / self.abs_weight_sum
+        } else {
+            Vec3::ZERO
+        }
+    }
+
+    // Standard error of the mean luminance, relative to the luminance itself.
+    // Infinite until there are enough samples to tell.
+    pub fn relative_error(&self) -> f32 {
+        if self.samples < 2 {
+            return f32::INFINITY;
+        }
+        let variance = self.squared_deviations / (self.samples - 1) as f32;
+        (variance / self.samples as f32).sqrt() / self.mean.max(MIN_LUMINANCE)
+    }
+}
@@ -0,0 +1,163 @@
+use core::f32;
+
+use glam::Vec2;
+
+// Reconstruction filter that weights the samples of a pixel by their offset from its center.
+// All of them are separable: the 2D weight is the product of the weights along x and y.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Filter {
+    // Every sample in the pixel counts the same
+    #[default]
+    Box,
+    // Weights fall off linearly up to one pixel away
+    Tent,
+    // Gaussian with a standard deviation of half a pixel, cut off at 1.5 pixels
+    Gaussian,
+    // Mitchell-Netravali with B = C = 1/3, a good balance between blurring and ringing
+    Mitchell,
+    // Windowed sinc with 2 lobes, the sharpest of these, but it can ring
+    Lanczos,
+}
+
+impl Filter {
+    // How far from the pixel center (in pixels) the filter is nonzero
+    pub fn radius(&self) -> f32 {
+        match self {
+            Filter::Box => 0.5,
+            Filter::Tent => 1.0,
+            Filter::Gaussian => 1.5,
+            Filter::Mitchell | Filter::Lanczos => 2.0,
+        }
+    }
+
+    pub fn evaluate(&self, offset: Vec2) -> f32 {
+        self.evaluate_1d(offset.x) * self.evaluate_1d(offset.y)
+    }
+
+    fn evaluate_1d(&self, x: f32) -> f32 {
+        let x = x.abs();
+        if x > self.radius() {
+            return 0.0;
+        }
+
+        match self {
+            Filter::Box => 1.0,
+            Filter::Tent => 1.0 - x,
+            Filter::Gaussian => {
+                const SIGMA: f32 = 0.5;
+                let gaussian = |x: f32| (-x * x / (2.0 * SIGMA * SIGMA)).exp();
+                // Shifted down so that it reaches 0 at the radius, instead of cutting off abruptly
+                (gaussian(x) - gaussian(self.radius())).max(0.0)
+            }
+            Filter::Mitchell => {
+                const B: f32 = 1.0 / 3.0;
+                const C: f32 = 1.0 / 3.0;
+                let value = if x < 1.0 {
+                    (12.0 - 9.0 * B - 6.0 * C) * x.powi(3)
+                        + (-18.0 + 12.0 * B + 6.0 * C) * x.powi(2)
+                        + (6.0 - 2.0 * B)
+                } else {
+                    (-B - 6.0 * C) * x.powi(3)
+                        + (6.0 * B + 30.0 * C) * x.powi(2)
+                        + (-12.0 * B - 48.0 * C) * x
+                        + (8.0 * B + 24.0 * C)
+                };
+                value / 6.0
+            }
+            Filter::Lanczos => sinc(x) * sinc(x / self.radius()),
+        }
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x < 1e-5 {
+        1.0
+    } else {
+        let x = x * f32::consts::PI;
+        x.sin() / x
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILTERS: [Filter; 5] = [
+        Filter::Box,
+        Filter::Tent,
+        Filter::Gaussian,
+        Filter::Mitchell,
+        Filter::Lanczos,
+    ];
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 1e-5, "{actual} != {expected}");
+    }
+
+    #[test]
+    fn test_radius() {
+        let radii = FILTERS.map(|filter| filter.radius());
+        assert_eq!(radii, [0.5, 1.0, 1.5, 2.0, 2.0]);
+    }
+
+    #[test]
+    fn test_zero_outside_radius() {
+        for filter in FILTERS {
+            let radius = filter.radius();
+            // Box is the only one that doesn't fall off to zero, its edge still counts
+            if filter != Filter::Box {
+                assert_close(filter.evaluate_1d(radius), 0.0);
+            }
+            for x in [radius.next_up(), radius + 0.1, radius * 2.0, 100.0] {
+                assert_eq!(filter.evaluate_1d(x), 0.0, "{filter:?} at {x}");
+                assert_eq!(filter.evaluate(Vec2::new(x, 0.0)), 0.0, "{filter:?} at {x}");
+                assert_eq!(
+                    filter.evaluate(Vec2::new(0.0, -x)),
+                    0.0,
+                    "{filter:?} at {x}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_symmetric() {
+        for filter in FILTERS {
+            for i in 0..=20 {
+                let x = filter.radius() * i as f32 / 20.0;
+                let offset = Vec2::new(x, 0.3 * x);
+                let weight = filter.evaluate(offset);
+                assert_eq!(filter.evaluate(-offset), weight, "{filter:?} at {offset}");
+                assert_eq!(
+                    filter.evaluate(Vec2::new(offset.y, offset.x)),
+                    weight,
+                    "{filter:?} at {offset}"
+                );
+                assert_eq!(
+                    filter.evaluate(Vec2::new(-offset.x, offset.y)),
+                    weight,
+                    "{filter:?} at {offset}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_mitchell_values() {
+        // With B = C = 1/3: (6 - 2B) / 6 at the center, B / 6 one pixel away
+        assert_close(Filter::Mitchell.evaluate_1d(0.0), 8.0 / 9.0);
+        assert_close(Filter::Mitchell.evaluate_1d(1.0), 1.0 / 18.0);
+        assert_close(Filter::Mitchell.evaluate_1d(-1.0), 1.0 / 18.0);
+        assert_close(Filter::Mitchell.evaluate_1d(2.0), 0.0);
+    }
+
+    #[test]
+    fn test_lanczos_values() {
+        // 1 at the center, and zero at every other whole pixel
+        assert_close(Filter::Lanczos.evaluate_1d(0.0), 1.0);
+        assert_close(Filter::Lanczos.evaluate_1d(1.0), 0.0);
+        assert_close(Filter::Lanczos.evaluate_1d(-1.0), 0.0);
+        assert_close(Filter::Lanczos.evaluate_1d(2.0), 0.0);
+        assert_close(Filter::Lanczos.evaluate(Vec2::ZERO), 1.0);
+    }
+}
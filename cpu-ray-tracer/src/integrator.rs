@@ -0,0 +1,19 @@
+// How the ray tracer computes the light arriving at the camera
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Integrator {
+    // Lambert shading against the lights, with hard shadows. Specular surfaces recursively reflect
+    // by their Ks color, and transparent ones split the light between reflection and refraction by
+    // the Fresnel term, until a path hits max_depth surfaces.
+    #[default]
+    Direct,
+    // Monte Carlo path tracing: diffuse bounces with cosine weighted sampling and Russian roulette,
+    // lit by the lights and the sky. Gives global illumination, but needs many samples per pixel.
+    Path,
+    // Grayscale ambient occlusion, to check the geometry without any lighting: the fraction of
+    // cosine weighted hemisphere rays from the first hit that don't hit anything within
+    // max_distance. White where nothing is hit.
+    AmbientOcclusion {
+        rays: u32,
+        max_distance: f32,
+    },
+}
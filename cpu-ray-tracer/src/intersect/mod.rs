@@ -1,4 +1,5 @@
-use glam::Vec2;
+use common::model::triangle::Triangle;
+use glam::{Vec2, Vec3};
 
 #[derive(Debug, Clone, Copy)]
 pub struct Intersection {
@@ -6,62 +7,220 @@ pub struct Intersection {
     #[allow(dead_code)]
     pub point: glam::Vec3,
     pub normal: glam::Vec3,
+    // The normal of the triangle's plane, rather than the interpolated one used for shading
+    pub geometric_normal: glam::Vec3,
     pub uv: glam::Vec2,
+    pub primitive: u32, // Index of the triangle that was hit, within whatever was intersected
+    pub instance: u32,  // Index of the instance that was hit, within whatever was intersected
 }
 
 pub trait Intersect {
     fn intersect(&self, ray: &crate::ray::Ray) -> Option<crate::intersect::Intersection>;
 }
 
-impl Intersect for common::model::triangle::Triangle {
+// Watertight ray/triangle intersection, from "Watertight Ray/Triangle Intersection" (Woop, Benthin
+// and Wald, JCGT 2013). The vertices are moved into a space where the ray starts at the origin and
+// points along +z, so the test becomes a 2D one against the signed edge functions U, V and W. Edges
+// that two triangles share compute to exactly the same value in both, so rays can't slip through
+// them.
+impl Intersect for Triangle {
     fn intersect(&self, ray: &crate::ray::Ray) -> Option<crate::intersect::Intersection> {
-        let e1 = self.v2.position - self.v1.position;
-        let e2 = self.v3.position - self.v1.position;
+        let crate::ray::Shear {
+            kx,
+            ky,
+            kz,
+            sx,
+            sy,
+            sz,
+        } = *ray.shear();
 
-        let ray_cross_e2 = ray.direction().cross(e2);
-        let det = e1.dot(ray_cross_e2);
+        let a = self.v1.position - ray.origin();
+        let b = self.v2.position - ray.origin();
+        let c = self.v3.position - ray.origin();
 
-        if det.abs() < f32::EPSILON {
-            // Ray is parallel to triangle
-            // println!("None triangle intersection");
-            return None;
+        let (ax, ay) = (a[kx] - sx * a[kz], a[ky] - sy * a[kz]);
+        let (bx, by) = (b[kx] - sx * b[kz], b[ky] - sy * b[kz]);
+        let (cx, cy) = (c[kx] - sx * c[kz], c[ky] - sy * c[kz]);
+
+        let mut u = cx * by - cy * bx;
+        let mut v = ax * cy - ay * cx;
+        let mut w = bx * ay - by * ax;
+
+        // Exactly on an edge, f32 can't tell which side the ray is on, so redo it in f64
+        if u == 0.0 || v == 0.0 || w == 0.0 {
+            let edge = |(x1, y1): (f32, f32), (x2, y2): (f32, f32)| {
+                (x1 as f64 * y2 as f64 - y1 as f64 * x2 as f64) as f32
+            };
+            u = edge((cx, cy), (bx, by));
+            v = edge((ax, ay), (cx, cy));
+            w = edge((bx, by), (ax, ay));
         }
 
-        let inv_det = 1.0 / det;
-        let s = ray.origin() - self.v1.position;
-        let u = inv_det * s.dot(ray_cross_e2);
-        if !(0.0..=1.0).contains(&u) {
+        if (u < 0.0 || v < 0.0 || w < 0.0) && (u > 0.0 || v > 0.0 || w > 0.0) {
             // Intersection lies outside the triangle
-            // println!("None triangle intersection");
             return None;
         }
 
-        let s_cross_e1 = s.cross(e1);
-        let v = inv_det * ray.direction().dot(s_cross_e1);
+        let det = u + v + w;
+        if det == 0.0 {
+            // Ray is parallel to triangle
+            return None;
+        }
 
-        if v < 0.0 || u + v > 1.0 {
-            // Intersection lies outside the triangle
-            // println!("None triangle intersection");
+        // Triangles are counter-clockwise by the time they get here, which makes det positive
+        // when the ray hits the front face
+        if ray.cull_mode().culls(det > 0.0) {
             return None;
         }
 
-        let t = inv_det * e2.dot(s_cross_e1);
-
-        if t > f32::EPSILON {
-            // ray intersection
-            // println!("Some triangle intersection");
-            let point = ray.at_t(t);
-            Some(crate::intersect::Intersection {
-                t,
-                point,
-                normal: self.v1.normal * (1.0 - u - v) + self.v2.normal * u + self.v3.normal * v,
-                uv: self.v1.uv.unwrap_or(Vec2::ZERO) * (1.0 - u - v)
-                    + self.v2.uv.unwrap_or(Vec2::ZERO) * u
-                    + self.v3.uv.unwrap_or(Vec2::ZERO) * v,
+        let (az, bz, cz) = (sz * a[kz], sz * b[kz], sz * c[kz]);
+        let t = (u * az + v * bz + w * cz) / det;
+        if !(t > ray.t_min() && t < ray.t_max()) {
+            return None;
+        }
+
+        let [v1, v2, v3] = [&self.v1, &self.v2, &self.v3];
+        let weights = glam::Vec3::new(u, v, w) / det;
+        // Interpolating the vertices is more accurate than origin + t * direction, which
+        // matters for offsetting rays that leave from this point
+        Some(crate::intersect::Intersection {
+            t,
+            point: v1.position * weights.x + v2.position * weights.y + v3.position * weights.z,
+            geometric_normal: (v2.position - v1.position)
+                .cross(v3.position - v1.position)
+                .normalize(),
+            normal: v1.normal * weights.x + v2.normal * weights.y + v3.normal * weights.z,
+            uv: v1.uv.unwrap_or(Vec2::ZERO) * weights.x
+                + v2.uv.unwrap_or(Vec2::ZERO) * weights.y
+                + v3.uv.unwrap_or(Vec2::ZERO) * weights.z,
+            primitive: 0,
+            instance: 0,
+        })
+    }
+}
+
+// Barycentric coordinates of the point where a ray crosses the plane of a triangle, even when that
+// point lies outside of the triangle. Returns None if the ray is parallel to the plane.
+pub fn plane_barycentrics(triangle: &Triangle, ray: &crate::ray::Ray) -> Option<Vec3> {
+    let e1 = triangle.v2.position - triangle.v1.position;
+    let e2 = triangle.v3.position - triangle.v1.position;
+
+    let ray_cross_e2 = ray.direction().cross(e2);
+    let det = e1.dot(ray_cross_e2);
+    if det.abs() < f32::EPSILON {
+        return None;
+    }
+
+    let inv_det = 1.0 / det;
+    let s = ray.origin() - triangle.v1.position;
+    let u = inv_det * s.dot(ray_cross_e2);
+    let v = inv_det * ray.direction().dot(s.cross(e1));
+    Some(Vec3::new(1.0 - u - v, u, v))
+}
+
+#[cfg(test)]
+mod tests {
+    use common::scene::CullMode;
+    use glam::Vec3;
+
+    use super::*;
+    use crate::{ray::Ray, test_scenes::scene_triangles};
+
+    // Points on the cube's shared edges and vertices, where a leaky test lets rays through
+    fn edge_points(triangles: &[Triangle]) -> Vec<Vec3> {
+        triangles
+            .iter()
+            .flat_map(|triangle| {
+                let [a, b, c] = [triangle.v1, triangle.v2, triangle.v3].map(|v| v.position);
+                [(a, b), (b, c), (c, a)]
             })
-        } else {
-            // println!("None triangle intersection");
-            None
+            .flat_map(|(a, b)| (0..=16).map(move |i| a.lerp(b, i as f32 / 16.0)))
+            .collect()
+    }
+
+    #[test]
+    fn test_rays_dont_slip_through_cube_edges() {
+        let triangles = scene_triangles("cube");
+        let hits = |ray: &Ray| triangles.iter().filter_map(|t| t.intersect(ray)).count();
+
+        for point in edge_points(&triangles) {
+            // Through the point and a few points inside the cube, from the inside out and from
+            // the outside in
+            for inside in [
+                Vec3::ZERO,
+                Vec3::new(0.1, -0.2, 0.3),
+                Vec3::new(-0.3, 0.2, 0.1),
+            ] {
+                for origin in [inside, point + 2.0 * (point - inside)] {
+                    let ray = Ray::new(origin, point - origin);
+                    assert!(hits(&ray) > 0, "ray from {origin} missed {point}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_grazing_rays_hit() {
+        let triangles = scene_triangles("cube");
+        // Skims the top face, almost parallel to it
+        let ray = Ray::new(Vec3::new(-10.0, 0.500001, 0.1), Vec3::new(1.0, -1e-7, 0.0));
+        assert!(triangles.iter().any(|t| t.intersect(&ray).is_some()));
+    }
+
+    #[test]
+    fn test_cull_modes() {
+        // The +x face of the cube, which faces out of it
+        let face: Vec<Triangle> = scene_triangles("cube")
+            .into_iter()
+            .filter(|t| [t.v1, t.v2, t.v3].iter().all(|v| v.position.x == 0.5))
+            .collect();
+        assert_eq!(face.len(), 2);
+        let hits = |ray: &Ray| face.iter().any(|t| t.intersect(ray).is_some());
+
+        let from_outside = Ray::new(Vec3::new(2.0, 0.1, 0.2), Vec3::NEG_X);
+        let from_inside = Ray::new(Vec3::new(0.0, 0.1, 0.2), Vec3::X);
+        for (cull_mode, outside_hits, inside_hits) in [
+            (CullMode::None, true, true),
+            (CullMode::Back, true, false),
+            (CullMode::Front, false, true),
+        ] {
+            assert_eq!(
+                hits(&from_outside.with_cull_mode(cull_mode)),
+                outside_hits,
+                "{cull_mode:?} from outside"
+            );
+            assert_eq!(
+                hits(&from_inside.with_cull_mode(cull_mode)),
+                inside_hits,
+                "{cull_mode:?} from inside"
+            );
+        }
+    }
+
+    #[test]
+    fn test_hits_only_within_interval() {
+        // Hits the +x face at t = 1.5 and the -x face at t = 2.5
+        let triangles = scene_triangles("cube");
+        let ray = Ray::new(Vec3::new(2.0, 0.1, 0.2), Vec3::NEG_X);
+        for (t_min, t_max, expected) in [
+            (0.0, f32::INFINITY, Some(1.5)),
+            (0.0, 1.499, None),
+            (0.0, 1.501, Some(1.5)),
+            (1.499, 2.0, Some(1.5)),
+            (1.501, f32::INFINITY, Some(2.5)),
+            (2.501, f32::INFINITY, None),
+        ] {
+            let ray = ray.with_interval(t_min, t_max);
+            let closest = triangles
+                .iter()
+                .filter_map(|t| t.intersect(&ray))
+                .map(|hit| hit.t)
+                .min_by(f32::total_cmp);
+            assert_eq!(
+                closest.map(|t| (t * 1000.0).round() / 1000.0),
+                expected,
+                "{t_min}..{t_max}"
+            );
         }
     }
 }
@@ -1,29 +1,145 @@
 use core::f32;
+use std::{
+    num::{NonZeroU32, NonZeroUsize},
+    ops::Range,
+    sync::Arc,
+    time::Instant,
+};
 
-use common::light;
+use common::{
+    light,
+    model::material::Material,
+    parallel,
+    surface::Surface,
+    wireframe::{Wireframe, edge_distance},
+};
 
 use crate::{
-    bvh::{Bvh, builder::BvhBuilder},
-    intersect::Intersect,
+    adaptive::AdaptiveSampling,
+    bvh::{
+        Bvh,
+        builder::{BvhBuilder, BvhSettings},
+        instance::BvhInstance,
+    },
+    estimate::PixelEstimate,
+    filter::Filter,
+    integrator::Integrator,
+    intersect::{Intersect, Intersection, plane_barycentrics},
     ray::Ray,
+    sampler::{Sampler, SamplerKind},
 };
 
-mod bvh;
+pub mod adaptive;
+pub mod bvh;
+mod estimate;
+pub mod filter;
+pub mod integrator;
 mod intersect;
+mod optics;
 mod ray;
+pub mod sampler;
+mod sampling;
+#[cfg(test)]
+mod test_scenes;
 
-const BIAS: f32 = 0.01;
+const BAND_HEIGHT: u32 = 16;
+// Paths are always traced for at least this many bounces before Russian roulette kicks in
+const RUSSIAN_ROULETTE_DEPTH: u32 = 3;
+const BACKGROUND: glam::Vec3 = glam::Vec3::new(0.5, 0.7, 0.9);
 
 pub struct CpuRayTracer {
     scene: common::scene::Scene,
-    bvh: Bvh,
+    bvh_settings: BvhSettings,
+    blases: Vec<Arc<Bvh>>, // A bottom level BVH per mesh, shared by all of its instances
+    tlas: Bvh<BvhInstance>, // The top level BVH, over the scene's instances
+    threads: NonZeroUsize,
+    wireframe: Option<Wireframe>,
+    integrator: Integrator,
+    max_depth: u32,
+    samples_per_pixel: NonZeroU32,
+    filter: Filter,
+    adaptive_sampling: Option<AdaptiveSampling>,
+    sampler: SamplerKind,
 }
 
 impl CpuRayTracer {
     pub fn new(scene: common::scene::Scene) -> Self {
-        let bvh =
-            BvhBuilder::new(scene.meshes().iter().flat_map(|m| &m.triangles).cloned()).build();
-        Self { scene, bvh }
+        Self::new_with_bvh_settings(scene, BvhSettings::default(), parallel::default_threads())
+    }
+
+    // The BVHs are built right away, on the same threads that render, so their settings can't
+    // change afterwards
+    pub fn new_with_bvh_settings(
+        scene: common::scene::Scene,
+        bvh_settings: BvhSettings,
+        threads: NonZeroUsize,
+    ) -> Self {
+        let blases = build_blases(&scene, bvh_settings, threads);
+        let tlas = build_tlas(&scene, &blases, bvh_settings, threads);
+        Self {
+            scene,
+            bvh_settings,
+            blases,
+            tlas,
+            threads,
+            wireframe: None,
+            integrator: Integrator::default(),
+            max_depth: 8,
+            samples_per_pixel: NonZeroU32::MIN,
+            filter: Filter::default(),
+            adaptive_sampling: None,
+            sampler: SamplerKind::default(),
+        }
+    }
+
+    // Moves an instance of a mesh. Only the top level BVH is rebuilt, the meshes' BVHs are kept.
+    pub fn set_instance_transform(&mut self, instance: usize, transform: glam::Affine3A) {
+        self.scene.set_instance_transform(instance, transform);
+        self.tlas = build_tlas(&self.scene, &self.blases, self.bvh_settings, self.threads);
+    }
+
+    pub fn with_threads(mut self, threads: NonZeroUsize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    pub fn with_wireframe(mut self, wireframe: Wireframe) -> Self {
+        self.wireframe = Some(wireframe);
+        self
+    }
+
+    pub fn with_integrator(mut self, integrator: Integrator) -> Self {
+        self.integrator = integrator;
+        self
+    }
+
+    // The maximum number of surfaces a path can hit, which also limits how deep reflections and
+    // refractions can recurse
+    pub fn with_max_depth(mut self, max_depth: u32) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    pub fn with_samples_per_pixel(mut self, samples_per_pixel: NonZeroU32) -> Self {
+        self.samples_per_pixel = samples_per_pixel;
+        self
+    }
+
+    // A single sample per pixel always goes through the center, which doesn't need a filter
+    pub fn with_filter(mut self, filter: Filter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn with_sampler(mut self, sampler: SamplerKind) -> Self {
+        self.sampler = sampler;
+        self
+    }
+
+    // Replaces the fixed number of samples per pixel with progressive rendering
+    pub fn with_adaptive_sampling(mut self, adaptive_sampling: AdaptiveSampling) -> Self {
+        self.adaptive_sampling = Some(adaptive_sampling);
+        self
     }
 
     pub fn render(&self, surface: &mut common::surface::Surface) {
@@ -37,82 +153,776 @@ impl CpuRayTracer {
         // let x = 200;
         // let y = 150;
 
-        for y in 0..height {
-            for x in 0..width {
-                let ndc = glam::Vec2::new(
-                    (x as f32 + 0.5) / (width as f32),
-                    -(y as f32 + 0.5) / (height as f32),
-                ) * 2.0
-                    + glam::Vec2::new(-1.0, 1.0);
-                let ray = ray::Ray::from_camera(camera, ndc);
-
-                // Disable the BVH for debug purposes
-
-                // let mut closest = f32::INFINITY;
-
-                // for m in self.scene.meshes() {
-                //     for t in &m.triangles {
-                //         if let Some(intersection) = t.intersect(&ray)
-                //             && intersection.t < closest
-                //         {
-                //             if closest.is_finite() {
-                //                 println!("overwriting pixel");
-                //             }
-                //             closest = intersection.t;
-                //             // Simple shading based on angle to lightray
-                //             let intensity = intersection
-                //                 .normal
-                //                 .dot(-ray.direction())
-                //                 .tap(|i| println!("unclamped intensity: {i}"))
-                //                 .clamp(0.0, 1.0);
-                //             *surface.get_mut(x, y) = (glam::Vec3::ONE * intensity).into();
-                //         }
-                //     }
-                // }
-
-                if let Some(intersection) = self.bvh.intersect(&ray) {
-                    let light_intensity: f32 = self
-                        .scene
-                        .lights()
-                        .iter()
-                        .map(|light| {
-                            let (light_ray, distance, intensity) = match light {
-                                light::Light::Sun {
-                                    direction,
-                                    intensity,
-                                } => {
-                                    let light_ray = Ray::new(
-                                        intersection.point + BIAS * intersection.normal,
-                                        *direction,
-                                    );
-                                    (light_ray, f32::INFINITY, *intensity)
-                                }
-                            };
-
-                            if let Some(intersection) = self.bvh.intersect(&light_ray) {
-                                0.0
-                            } else {
-                                intensity
-                                    * intersection
-                                        .normal
-                                        .dot(*light_ray.direction())
-                                        .clamp(0.0, 1.0)
-                            }
-                        })
-                        .sum();
-
-                    // Simple shading based on angle to lightray
-                    let intensity = intersection.normal.dot(-ray.direction()).clamp(0.0, 1.0);
-                    let color = ((intersection.uv.x * 16.0).round()
-                        + (intersection.uv.y * 16.0).round())
-                        % 2.0;
-
-                    *surface.get_mut(x, y) =
-                        (glam::Vec3::ONE * (0.5 + color / 2.0) * (light_intensity)).into();
+        // The ray through a point on the surface, in pixels
+        let camera_ray = |x: f32, y: f32| {
+            let ndc = glam::Vec2::new(x / (width as f32), -y / (height as f32)) * 2.0
+                + glam::Vec2::new(-1.0, 1.0);
+            // Culling only applies to what the camera sees, both sides of a triangle cast shadows
+            ray::Ray::from_camera(camera, ndc).with_cull_mode(self.scene.cull_mode())
+        };
+
+        if let Some(adaptive) = &self.adaptive_sampling {
+            self.render_adaptive(surface, adaptive, &camera_ray);
+            return;
+        }
+
+        // Every pixel only depends on its own coordinates, so bands of rows can be traced in parallel
+        // and the result is the same for any number of threads
+        parallel::for_each(surface.bands_mut(BAND_HEIGHT), self.threads, |mut band| {
+            let samples = self.samples_per_pixel.get();
+            let mut sampler = self.sampler.create(samples);
+            for y in band.y()..band.y() + band.height() {
+                for x in 0..width {
+                    let (pixel_x, pixel_y) = (x as f32 + 0.5, y as f32 + 0.5);
+                    let ray = camera_ray(pixel_x, pixel_y);
+
+                    // Disable the BVH for debug purposes
+
+                    // let mut closest = f32::INFINITY;
+
+                    // for m in self.scene.meshes() {
+                    //     for t in &m.triangles {
+                    //         if let Some(intersection) = t.intersect(&ray)
+                    //             && intersection.t < closest
+                    //         {
+                    //             if closest.is_finite() {
+                    //                 println!("overwriting pixel");
+                    //             }
+                    //             closest = intersection.t;
+                    //             // Simple shading based on angle to lightray
+                    //             let intensity = intersection
+                    //                 .normal
+                    //                 .dot(-ray.direction())
+                    //                 .tap(|i| println!("unclamped intensity: {i}"))
+                    //                 .clamp(0.0, 1.0);
+                    //             *band.get_mut(x, y) = (glam::Vec3::ONE * intensity).into();
+                    //         }
+                    //     }
+                    // }
+
+                    let pixel = glam::UVec2::new(x, y);
+                    let color = if samples == 1 {
+                        sampler.start_sample(pixel, 0);
+                        self.radiance(&ray, sampler.as_mut())
+                    } else {
+                        let mut estimate = PixelEstimate::default();
+                        self.sample_pixel(
+                            &mut estimate,
+                            sampler.as_mut(),
+                            pixel,
+                            0..samples,
+                            |offset| camera_ray(pixel_x + offset.x, pixel_y + offset.y),
+                        );
+                        estimate.color()
+                    };
+
+                    *band.get_mut(x, y) = self.overlay_wireframe(color, x, y, &camera_ray).into();
+                }
+            }
+        });
+    }
+
+    // Refines the pixels in passes, only adding samples where they're still too noisy.
+    // Samples only depend on the pixel and the sample index, so without a time budget the result
+    // is the same for any number of threads.
+    fn render_adaptive(
+        &self,
+        surface: &mut common::surface::Surface,
+        adaptive: &AdaptiveSampling,
+        camera_ray: &(impl Fn(f32, f32) -> Ray + Sync),
+    ) {
+        let width = surface.width();
+        let height = surface.height();
+        let deadline = adaptive.time_budget.map(|budget| Instant::now() + budget);
+        let out_of_time = || deadline.is_some_and(|deadline| Instant::now() >= deadline);
+
+        let batch_size = adaptive.batch_size.get();
+        // The estimate of every pixel, and the index of its next sample
+        let mut pixels = Surface::filled(width, height, (PixelEstimate::default(), 0));
+
+        let mut first_pass = true;
+        loop {
+            parallel::for_each(pixels.bands_mut(BAND_HEIGHT), self.threads, |mut band| {
+                let mut sampler = self.sampler.create(batch_size);
+                for y in band.y()..band.y() + band.height() {
+                    // Every pixel gets its first batch, even when the time is up, so none of them
+                    // are left without any samples
+                    if !first_pass && out_of_time() {
+                        return;
+                    }
+                    for x in 0..width {
+                        let (estimate, next_sample) = band.get_mut(x, y);
+                        if let Some(samples) = adaptive.next_batch(estimate, *next_sample) {
+                            let (pixel_x, pixel_y) = (x as f32 + 0.5, y as f32 + 0.5);
+                            *next_sample = samples.end;
+                            self.sample_pixel(
+                                estimate,
+                                sampler.as_mut(),
+                                glam::UVec2::new(x, y),
+                                samples,
+                                |offset| camera_ray(pixel_x + offset.x, pixel_y + offset.y),
+                            );
+                        }
+                    }
+                }
+            });
+
+            let converged = (0..height).all(|y| {
+                (0..width).all(|x| {
+                    let (estimate, next_sample) = pixels.get(x, y);
+                    adaptive.next_batch(&estimate, next_sample).is_none()
+                })
+            });
+            if converged || out_of_time() {
+                break;
+            }
+            first_pass = false;
+        }
+
+        parallel::for_each(surface.bands_mut(BAND_HEIGHT), self.threads, |mut band| {
+            for y in band.y()..band.y() + band.height() {
+                for x in 0..width {
+                    let color = pixels.get(x, y).0.color();
+                    *band.get_mut(x, y) = self.overlay_wireframe(color, x, y, camera_ray).into();
+                }
+            }
+        });
+    }
+
+    // Adds samples to a pixel, spread out over the filter's footprint and weighted by the filter.
+    // Every pixel only uses its own samples, so pixels stay independent of each other.
+    fn sample_pixel(
+        &self,
+        estimate: &mut PixelEstimate,
+        sampler: &mut dyn Sampler,
+        pixel: glam::UVec2,
+        samples: Range<u32>,
+        camera_ray: impl Fn(glam::Vec2) -> Ray,
+    ) {
+        let radius = self.filter.radius();
+        for index in samples {
+            sampler.start_sample(pixel, index);
+            let offset = (sampler.next_2d() * 2.0 - 1.0) * radius;
+            let weight = self.filter.evaluate(offset);
+            if weight == 0.0 {
+                continue;
+            }
+            estimate.add(self.radiance(&camera_ray(offset), sampler), weight);
+        }
+    }
+
+    fn overlay_wireframe(
+        &self,
+        color: glam::Vec3,
+        x: u32,
+        y: u32,
+        camera_ray: &impl Fn(f32, f32) -> Ray,
+    ) -> glam::Vec3 {
+        let Some(wireframe) = &self.wireframe else {
+            return color;
+        };
+        let (pixel_x, pixel_y) = (x as f32 + 0.5, y as f32 + 0.5);
+        match self.tlas.intersect(&camera_ray(pixel_x, pixel_y)) {
+            Some(intersection) => {
+                let distance = self.edge_distance(&intersection, |dx, dy| {
+                    camera_ray(pixel_x + dx, pixel_y + dy)
+                });
+                wireframe.shade(color, BACKGROUND, distance)
+            }
+            None => wireframe.shade(color, BACKGROUND, f32::INFINITY),
+        }
+    }
+
+    // The light arriving along a camera ray
+    fn radiance(&self, ray: &Ray, sampler: &mut dyn Sampler) -> glam::Vec3 {
+        match self.integrator {
+            Integrator::Direct => self.trace_whitted(ray, 0),
+            Integrator::Path => self.trace_path(ray, sampler),
+            Integrator::AmbientOcclusion { rays, max_distance } => {
+                glam::Vec3::splat(self.ambient_occlusion(ray, sampler, rays, max_distance))
+            }
+        }
+    }
+
+    // The fraction of the hemisphere above the first hit that's open, weighted by the cosine
+    fn ambient_occlusion(
+        &self,
+        ray: &Ray,
+        sampler: &mut dyn Sampler,
+        rays: u32,
+        max_distance: f32,
+    ) -> f32 {
+        let Some(intersection) = self.tlas.intersect(ray) else {
+            return 1.0;
+        };
+        if rays == 0 {
+            return 1.0;
+        }
+
+        // The side of the surface that the camera sees
+        let normal = if intersection.normal.dot(*ray.direction()) > 0.0 {
+            -intersection.normal
+        } else {
+            intersection.normal
+        };
+
+        let open = (0..rays)
+            .filter(|_| {
+                let direction = sampling::cosine_hemisphere(normal, sampler.next_2d());
+                let occlusion_ray =
+                    Ray::from_surface(intersection.point, intersection.geometric_normal, direction)
+                        .with_interval(0.0, max_distance);
+                !self.tlas.occluded(&occlusion_ray)
+            })
+            .count();
+        open as f32 / rays as f32
+    }
+
+    // Light from the scene's lights that reaches a point, weighted by the angle with the normal
+    fn direct_light(&self, intersection: &Intersection, normal: glam::Vec3) -> f32 {
+        self.scene
+            .lights()
+            .iter()
+            .map(|light| {
+                let (light_ray, intensity) = match light {
+                    light::Light::Sun {
+                        direction,
+                        intensity,
+                    } => {
+                        // The sun is infinitely far away, so the whole ray counts
+                        let light_ray = Ray::from_surface(
+                            intersection.point,
+                            intersection.geometric_normal,
+                            *direction,
+                        );
+                        (light_ray, *intensity)
+                    }
+                };
+
+                if self.tlas.occluded(&light_ray) {
+                    0.0
+                } else {
+                    intensity * normal.dot(*light_ray.direction()).clamp(0.0, 1.0)
+                }
+            })
+            .sum()
+    }
+
+    // Lambert shading against the lights, plus recursive mirror reflections and refraction
+    fn trace_whitted(&self, ray: &Ray, depth: u32) -> glam::Vec3 {
+        let Some(intersection) = self.tlas.intersect(ray) else {
+            return BACKGROUND;
+        };
+        let material = self.material(&intersection);
+
+        let local = albedo(&intersection)
+            * material.diffuse
+            * self.direct_light(&intersection, intersection.normal);
+        if material.specular == glam::Vec3::ZERO && material.transparency == 0.0 {
+            return local;
+        }
+
+        let (normal, eta) = facing(ray, &intersection, material);
+        let direction = *ray.direction();
+        let fresnel = optics::fresnel(direction, normal, eta);
+
+        // Paths that are too long are cut off, and don't get any light from further on
+        let trace = |direction: glam::Vec3| {
+            if depth + 1 < self.max_depth {
+                let ray =
+                    Ray::from_surface(intersection.point, intersection.geometric_normal, direction);
+                self.trace_whitted(&ray, depth + 1)
+            } else {
+                glam::Vec3::ZERO
+            }
+        };
+
+        // The opaque part of the surface reflects by its specular color, and the transparent part
+        // splits the light up between reflection and refraction
+        let opacity = 1.0 - material.transparency;
+        let reflection_weight = opacity * material.specular + material.transparency * fresnel;
+        let reflected = if reflection_weight != glam::Vec3::ZERO {
+            trace(optics::reflect(direction, normal))
+        } else {
+            glam::Vec3::ZERO
+        };
+        let refracted = match optics::refract(direction, normal, eta) {
+            Some(refracted) if material.transparency > 0.0 => trace(refracted),
+            _ => glam::Vec3::ZERO,
+        };
+
+        opacity * local
+            + reflection_weight * reflected
+            + material.transparency * (1.0 - fresnel) * refracted
+    }
+
+    fn trace_path(&self, camera_ray: &Ray, sampler: &mut dyn Sampler) -> glam::Vec3 {
+        let mut radiance = glam::Vec3::ZERO;
+        // How much of the light arriving at the current vertex makes it back to the camera
+        let mut throughput = glam::Vec3::ONE;
+        let mut next_ray = None;
+
+        for depth in 0..self.max_depth {
+            let ray = next_ray.as_ref().unwrap_or(camera_ray);
+            let Some(intersection) = self.tlas.intersect(ray) else {
+                // The sky lights the scene too
+                return radiance + throughput * BACKGROUND;
+            };
+
+            // Bounce off the side of the surface that the ray arrived at
+            let material = self.material(&intersection);
+            let (normal, eta) = facing(ray, &intersection, material);
+            let direction = *ray.direction();
+
+            // Pick one of the ways the surface can scatter light at random. The probabilities
+            // match the weights in the Whitted integrator, so those cancel out.
+            let direction = if sampler.next_1d() < material.transparency {
+                // Glass: reflect or refract, depending on the Fresnel term
+                let fresnel = optics::fresnel(direction, normal, eta);
+                match optics::refract(direction, normal, eta) {
+                    Some(refracted) if sampler.next_1d() >= fresnel => refracted,
+                    _ => optics::reflect(direction, normal),
+                }
+            } else {
+                let albedo = albedo(&intersection) * material.diffuse;
+
+                // Sun lights can't be hit by chance, so sample them explicitly at every vertex
+                radiance += throughput * albedo * self.direct_light(&intersection, normal);
+
+                let specular_probability = material.specular.max_element().min(0.5);
+                if sampler.next_1d() < specular_probability {
+                    throughput *= material.specular / specular_probability;
+                    optics::reflect(direction, normal)
                 } else {
-                    *surface.get_mut(x, y) = glam::Vec3::new(0.5, 0.7, 0.9).into();
+                    // Sampling proportional to the cosine cancels out the cosine term and the
+                    // 1/pi of the Lambertian BRDF, which only leaves the albedo
+                    throughput *= albedo / (1.0 - specular_probability);
+                    sampling::cosine_hemisphere(normal, sampler.next_2d())
+                }
+            };
+
+            // Russian roulette: end paths that can't contribute much anymore, and make up for it
+            // by weighting the ones that survive, which keeps the estimate unbiased
+            if depth >= RUSSIAN_ROULETTE_DEPTH {
+                let survival = throughput.max_element().min(0.95);
+                if sampler.next_1d() >= survival {
+                    break;
                 }
+                throughput /= survival;
             }
+
+            next_ray = Some(Ray::from_surface(
+                intersection.point,
+                intersection.geometric_normal,
+                direction,
+            ));
+        }
+
+        radiance
+    }
+
+    fn material(&self, intersection: &Intersection) -> &Material {
+        let mesh = self.tlas.primitive(intersection.instance).mesh;
+        &self.scene.meshes()[mesh].material
+    }
+
+    // Distance in pixels from a camera ray's intersection to the closest edge of the triangle it hit.
+    // The neighbouring pixels' rays are intersected with the plane of the same triangle, to find
+    // out how fast the barycentric coordinates change from one pixel to the next.
+    fn edge_distance(
+        &self,
+        intersection: &Intersection,
+        camera_ray: impl Fn(f32, f32) -> Ray,
+    ) -> f32 {
+        // Barycentric coordinates don't change under the instance's transform, so the rays are
+        // intersected with the triangle in the mesh's own space
+        let instance = self.tlas.primitive(intersection.instance);
+        let triangle = instance.blas().primitive(intersection.primitive);
+        let barycentrics =
+            |dx, dy| plane_barycentrics(triangle, &instance.to_object_space(&camera_ray(dx, dy)));
+
+        match (
+            barycentrics(0.0, 0.0),
+            barycentrics(1.0, 0.0),
+            barycentrics(0.0, 1.0),
+        ) {
+            (Some(center), Some(right), Some(down)) => edge_distance(center, right, down),
+            // Seen edge-on, the whole triangle is an edge
+            _ => 0.0,
         }
     }
 }
+
+fn build_blases(
+    scene: &common::scene::Scene,
+    settings: BvhSettings,
+    threads: NonZeroUsize,
+) -> Vec<Arc<Bvh>> {
+    // The meshes' BVHs are built in parallel, and split the threads between them
+    let meshes = scene.meshes();
+    let mesh_threads =
+        NonZeroUsize::new(meshes.len().min(threads.get())).unwrap_or(NonZeroUsize::MIN);
+    let threads_per_mesh = NonZeroUsize::new(threads.get() / mesh_threads.get()).unwrap();
+
+    let mut blases = vec![None; meshes.len()];
+    parallel::for_each(
+        meshes.iter().zip(&mut blases),
+        mesh_threads,
+        |(mesh, blas)| {
+            let bvh = BvhBuilder::new(mesh.counter_clockwise_triangles())
+                .with_settings(settings)
+                .with_threads(threads_per_mesh)
+                .build();
+            *blas = Some(Arc::new(bvh));
+        },
+    );
+    blases.into_iter().map(Option::unwrap).collect()
+}
+
+fn build_tlas(
+    scene: &common::scene::Scene,
+    blases: &[Arc<Bvh>],
+    settings: BvhSettings,
+    threads: NonZeroUsize,
+) -> Bvh<BvhInstance> {
+    let instances = scene
+        .instances()
+        .iter()
+        // Meshes without triangles have nothing to hit, and no bounding box
+        .filter(|instance| !blases[instance.mesh].is_empty())
+        .map(|instance| {
+            BvhInstance::new(
+                instance.mesh,
+                blases[instance.mesh].clone(),
+                instance.transform,
+            )
+        });
+    // Instances are few and usually large, so leaves hold a single one
+    BvhBuilder::new(instances)
+        .with_settings(BvhSettings {
+            max_leaf_size: NonZeroU32::MIN,
+            ..settings
+        })
+        .with_threads(threads)
+        .build()
+}
+
+// The normal on the side of the surface that the ray arrives at, and the ratio of the indices of
+// refraction on either side (from / to). Normals point out of the mesh.
+fn facing(ray: &Ray, intersection: &Intersection, material: &Material) -> (glam::Vec3, f32) {
+    if intersection.normal.dot(*ray.direction()) > 0.0 {
+        (-intersection.normal, material.index_of_refraction)
+    } else {
+        (intersection.normal, 1.0 / material.index_of_refraction)
+    }
+}
+
+// Checkerboard pattern from the texture coordinates
+fn albedo(intersection: &Intersection) -> glam::Vec3 {
+    let color = ((intersection.uv.x * 16.0).round() + (intersection.uv.y * 16.0).round()) % 2.0;
+    glam::Vec3::ONE * (0.5 + color / 2.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use common::{
+        camera::Camera,
+        light::Light,
+        model::triangle::Mesh,
+        scene::SceneBuilder,
+        surface::{Surface, format::RGBA8},
+    };
+    use glam::{Affine3A, Quat, Vec3};
+
+    use super::*;
+    use crate::test_scenes::{cube, scene_meshes};
+
+    fn render_cube(threads: usize, configure: impl Fn(CpuRayTracer) -> CpuRayTracer) -> Surface {
+        let scene = SceneBuilder::new()
+            .with_camera(Camera::look_at(
+                Vec3::new(2.0, 1.0, 1.0),
+                Vec3::ZERO,
+                Vec3::Y,
+                80.0,
+                16.0 / 9.0,
+            ))
+            .add_meshes(scene_meshes("cube"))
+            // A second mesh, so there's more than one BVH to build in parallel
+            .add_instanced_mesh(
+                cube(),
+                [Affine3A::from_scale_rotation_translation(
+                    Vec3::splat(0.4),
+                    Quat::from_rotation_y(0.5),
+                    Vec3::new(0.0, 0.8, -0.8),
+                )],
+            )
+            .add_light(Light::Sun {
+                direction: Vec3::ONE.normalize(),
+                intensity: 0.8,
+            })
+            .build();
+
+        let threads = NonZeroUsize::new(threads).unwrap();
+        let mut surface = Surface::new(96, 54);
+        configure(CpuRayTracer::new_with_bvh_settings(
+            scene,
+            BvhSettings::default(),
+            threads,
+        ))
+        .render(&mut surface);
+        surface
+    }
+
+    fn assert_threads_match_single_threaded(configure: impl Fn(CpuRayTracer) -> CpuRayTracer) {
+        let single = render_cube(1, &configure);
+        for threads in [2, 3, 8] {
+            let multi = render_cube(threads, &configure);
+            for y in 0..single.height() {
+                for x in 0..single.width() {
+                    assert_eq!(single.get(x, y), multi.get(x, y), "pixel ({x}, {y})");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_threads_match_single_threaded() {
+        assert_threads_match_single_threaded(|renderer| renderer);
+    }
+
+    #[test]
+    fn test_instances_match_transformed_meshes() {
+        // Including a non-uniform scale, and one that mirrors the cube inside out
+        let transforms = [
+            Affine3A::from_translation(Vec3::new(-1.5, 0.0, 0.0)),
+            Affine3A::from_scale_rotation_translation(
+                Vec3::splat(0.5),
+                Quat::from_rotation_y(0.6),
+                Vec3::new(1.0, 0.5, 0.0),
+            ),
+            Affine3A::from_scale_rotation_translation(
+                Vec3::new(-0.5, 1.5, 0.75),
+                Quat::from_rotation_x(0.3),
+                Vec3::new(0.0, -0.5, 1.5),
+            ),
+        ];
+        let builder = || {
+            SceneBuilder::new()
+                .with_camera(Camera::look_at(
+                    Vec3::new(3.0, 2.0, 4.0),
+                    Vec3::ZERO,
+                    Vec3::Y,
+                    80.0,
+                    16.0 / 9.0,
+                ))
+                .add_light(Light::Sun {
+                    direction: Vec3::ONE.normalize(),
+                    intensity: 0.8,
+                })
+        };
+        let render = |renderer: &CpuRayTracer| {
+            let mut surface = Surface::new(96, 54);
+            renderer.render(&mut surface);
+            surface
+        };
+
+        // The first instance starts out somewhere else, so moving it is covered too
+        let mut instanced = CpuRayTracer::new(
+            builder()
+                .add_instanced_mesh(
+                    cube(),
+                    [Affine3A::from_translation(Vec3::splat(10.0))]
+                        .into_iter()
+                        .chain(transforms[1..].iter().copied()),
+                )
+                .build(),
+        );
+        instanced.set_instance_transform(0, transforms[0]);
+        let instanced = render(&instanced);
+
+        let transformed = builder()
+            .add_meshes(
+                transforms
+                    .iter()
+                    .map(|transform| {
+                        let mut mesh = cube();
+                        mesh.triangles = mesh
+                            .triangles
+                            .iter()
+                            .map(|triangle| triangle.transformed(transform))
+                            .collect();
+                        mesh
+                    })
+                    .collect(),
+            )
+            .build();
+        let transformed = render(&CpuRayTracer::new(transformed));
+
+        // Hits are found in different spaces, so they can differ by rounding, mostly on edges
+        let mut differing = 0;
+        for y in 0..instanced.height() {
+            for x in 0..instanced.width() {
+                let (a, b) = (instanced.get(x, y), transformed.get(x, y));
+                let difference = [(a.r, b.r), (a.g, b.g), (a.b, b.b)]
+                    .into_iter()
+                    .map(|(a, b)| a.abs_diff(b))
+                    .max()
+                    .unwrap();
+                if difference > 2 {
+                    differing += 1;
+                }
+            }
+        }
+        assert!(
+            differing <= (instanced.width() * instanced.height()) / 100,
+            "{differing} pixels differ"
+        );
+    }
+
+    #[test]
+    fn test_adaptive_threads_match_single_threaded() {
+        assert_threads_match_single_threaded(|renderer| {
+            renderer
+                .with_integrator(Integrator::Path)
+                .with_adaptive_sampling(AdaptiveSampling {
+                    max_samples: NonZeroU32::new(64).unwrap(),
+                    ..Default::default()
+                })
+        });
+    }
+
+    // A plain white version of a mesh: diffuse only, with an albedo of 1 everywhere on the
+    // checkerboard
+    fn whiten(mesh: &Mesh) -> Mesh {
+        let triangles = mesh
+            .triangles
+            .iter()
+            .map(|triangle| {
+                let mut triangle = *triangle;
+                for vertex in [&mut triangle.v1, &mut triangle.v2, &mut triangle.v3] {
+                    vertex.uv = Some(glam::Vec2::new(1.0 / 16.0, 0.0));
+                }
+                triangle
+            })
+            .collect();
+        Mesh::new(triangles).with_winding(mesh.winding)
+    }
+
+    // The average radiance that the path tracer finds along a ray
+    fn mean_path_radiance(renderer: &CpuRayTracer, ray: &Ray, samples: u32) -> Vec3 {
+        let mut sampler = SamplerKind::Independent.create(samples);
+        let total: Vec3 = (0..samples)
+            .map(|i| {
+                sampler.start_sample(glam::UVec2::ZERO, i);
+                renderer.trace_path(ray, sampler.as_mut())
+            })
+            .sum();
+        total / samples as f32
+    }
+
+    fn assert_close_to_sky(radiance: Vec3, tolerance: f32) {
+        let error = (radiance / BACKGROUND - 1.0).abs().max_element();
+        assert!(error < tolerance, "{radiance} isn't {BACKGROUND}");
+    }
+
+    #[test]
+    fn test_white_furnace() {
+        // Without lights, a surface that reflects all light looks just like the sky around it. The
+        // teapot is concave, so some of the paths bounce off it more than once.
+        let teapot = scene_meshes("teapot");
+        let scene = SceneBuilder::new()
+            .add_meshes(teapot.iter().map(whiten).collect())
+            .build();
+        let renderer = CpuRayTracer::new(scene).with_integrator(Integrator::Path);
+
+        for i in 0..8 {
+            let angle = i as f32 * 0.8;
+            let origin = Vec3::new(
+                angle.cos() * 200.0,
+                30.0 + i as f32 * 10.0,
+                angle.sin() * 200.0,
+            );
+            let ray = Ray::new(origin, Vec3::new(0.0, 40.0, 0.0) - origin);
+            assert!(renderer.tlas.intersect(&ray).is_some());
+            assert_close_to_sky(mean_path_radiance(&renderer, &ray, 4000), 0.02);
+        }
+    }
+
+    #[test]
+    fn test_russian_roulette_is_unbiased() {
+        // A stack of clear sheets that don't bend light, which every path goes straight through: 20
+        // surfaces, far more than the depth where Russian roulette starts. The paths that survive
+        // make up for the ones that end early, so on average all of the sky still gets through.
+        let cube = cube().with_material(Material {
+            transparency: 1.0,
+            index_of_refraction: 1.0,
+            ..Material::default()
+        });
+        let sheets = (0..10).map(|i| {
+            Affine3A::from_scale_rotation_translation(
+                Vec3::new(1.0, 0.1, 1.0),
+                Quat::IDENTITY,
+                Vec3::new(0.0, i as f32 * 0.5, 0.0),
+            )
+        });
+        let scene = SceneBuilder::new().add_instanced_mesh(cube, sheets).build();
+        let ray = Ray::new(Vec3::new(0.1, 10.0, 0.2), Vec3::NEG_Y);
+        let renderer = |max_depth| {
+            CpuRayTracer::new(scene.clone())
+                .with_integrator(Integrator::Path)
+                .with_max_depth(max_depth)
+        };
+
+        assert_close_to_sky(mean_path_radiance(&renderer(21), &ray, 40_000), 0.03);
+        // Paths that are cut off by max_depth don't get any light, whatever the roulette does
+        assert_eq!(mean_path_radiance(&renderer(20), &ray, 1000), Vec3::ZERO);
+    }
+
+    #[test]
+    fn test_time_budget_samples_every_pixel() {
+        // Out of time right away, but every pixel still gets its first batch
+        let surface = render_cube(3, |renderer| {
+            renderer.with_adaptive_sampling(AdaptiveSampling {
+                time_budget: Some(Duration::ZERO),
+                ..Default::default()
+            })
+        });
+        for y in 0..surface.height() {
+            for x in 0..surface.width() {
+                assert_ne!(surface.get(x, y), RGBA8::BLACK, "pixel ({x}, {y})");
+            }
+        }
+    }
+
+    // Ambient occlusion where a ray hits a cube, placed by transform
+    fn cube_ambient_occlusion(transform: Affine3A, ray: Ray, max_distance: f32) -> f32 {
+        let scene = SceneBuilder::new()
+            .add_instanced_mesh(cube(), [transform])
+            .build();
+
+        let mut sampler = SamplerKind::Independent.create(1);
+        sampler.start_sample(glam::UVec2::ZERO, 0);
+        CpuRayTracer::new(scene).ambient_occlusion(&ray, sampler.as_mut(), 256, max_distance)
+    }
+
+    #[test]
+    fn test_ambient_occlusion() {
+        // On top of a wide, flat slab, nothing is in the way
+        let slab = Affine3A::from_scale(Vec3::new(100.0, 0.01, 100.0));
+        let from_above = Ray::new(Vec3::new(0.3, 5.0, 0.2), Vec3::NEG_Y);
+        assert_eq!(cube_ambient_occlusion(slab, from_above, f32::INFINITY), 1.0);
+
+        // Inside of the cube, everything is. The ray stays clear of the diagonals of the faces.
+        let from_inside = Ray::new(Vec3::new(0.0, 0.02, 0.05), Vec3::X);
+        let inside =
+            |max_distance| cube_ambient_occlusion(Affine3A::IDENTITY, from_inside, max_distance);
+        assert_eq!(inside(f32::INFINITY), 0.0);
+
+        // Unless the rays are too short to reach the other faces, which are at least 0.45 away
+        assert_eq!(inside(0.4), 1.0);
+        let partly = inside(0.75);
+        assert!(0.0 < partly && partly < 1.0, "{partly}");
+    }
+}
@@ -0,0 +1,85 @@
+use glam::Vec3;
+
+// Mirrors a direction around the normal
+pub fn reflect(direction: Vec3, normal: Vec3) -> Vec3 {
+    direction - 2.0 * direction.dot(normal) * normal
+}
+
+// Bends a direction as it passes into a medium. The normal points back towards where the
+// direction is coming from, and eta is the ratio of the indices of refraction (from / to).
+// Returns None for total internal reflection.
+pub fn refract(direction: Vec3, normal: Vec3, eta: f32) -> Option<Vec3> {
+    let cos_incident = -direction.dot(normal);
+    let sin2_transmitted = eta * eta * (1.0 - cos_incident * cos_incident);
+    if sin2_transmitted > 1.0 {
+        return None;
+    }
+    let cos_transmitted = (1.0 - sin2_transmitted).sqrt();
+    Some((eta * direction + (eta * cos_incident - cos_transmitted) * normal).normalize())
+}
+
+// The fraction of light that a dielectric reflects, the rest is transmitted.
+// Uses the same conventions as refract, and averages both polarizations.
+pub fn fresnel(direction: Vec3, normal: Vec3, eta: f32) -> f32 {
+    let cos_incident = -direction.dot(normal);
+    let sin2_transmitted = eta * eta * (1.0 - cos_incident * cos_incident);
+    if sin2_transmitted > 1.0 {
+        // Total internal reflection
+        return 1.0;
+    }
+    let cos_transmitted = (1.0 - sin2_transmitted).sqrt();
+
+    let parallel = (cos_incident - eta * cos_transmitted) / (cos_incident + eta * cos_transmitted);
+    let perpendicular =
+        (eta * cos_incident - cos_transmitted) / (eta * cos_incident + cos_transmitted);
+    (parallel * parallel + perpendicular * perpendicular) / 2.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reflect() {
+        let direction = Vec3::new(1.0, -1.0, 0.0).normalize();
+        let reflected = reflect(direction, Vec3::Y);
+        assert!(reflected.distance(Vec3::new(1.0, 1.0, 0.0).normalize()) < 1e-6);
+    }
+
+    #[test]
+    fn test_refract_follows_snells_law() {
+        let (n1, n2) = (1.0, 1.5);
+        let direction = Vec3::new(0.5, -1.0, 0.0).normalize();
+        let refracted = refract(direction, Vec3::Y, n1 / n2).unwrap();
+
+        let sin_incident = direction.cross(Vec3::Y).length();
+        let sin_transmitted = refracted.cross(Vec3::Y).length();
+        assert!((n1 * sin_incident - n2 * sin_transmitted).abs() < 1e-6);
+        // Bent towards the normal, and still on the far side of the surface
+        assert!(refracted.y < 0.0 && refracted.x > 0.0);
+        assert!(sin_transmitted < sin_incident);
+    }
+
+    #[test]
+    fn test_fresnel_at_normal_incidence() {
+        for (n1, n2) in [(1.0, 1.5), (1.5, 1.0), (1.0, 1.33)] {
+            let reflectance = fresnel(Vec3::NEG_Y, Vec3::Y, n1 / n2);
+            let expected = ((n1 - n2) / (n1 + n2)).powi(2);
+            assert!((reflectance - expected).abs() < 1e-6, "{n1} -> {n2}");
+        }
+    }
+
+    #[test]
+    fn test_total_internal_reflection() {
+        // From glass into air, past the critical angle of about 42 degrees
+        let eta = 1.5;
+        let direction = Vec3::new(1.0, -1.0, 0.0).normalize();
+        assert_eq!(refract(direction, Vec3::Y, eta), None);
+        assert_eq!(fresnel(direction, Vec3::Y, eta), 1.0);
+
+        // Below the critical angle, some light gets through
+        let direction = Vec3::new(0.5, -1.0, 0.0).normalize();
+        assert!(refract(direction, Vec3::Y, eta).is_some());
+        assert!(fresnel(direction, Vec3::Y, eta) < 1.0);
+    }
+}
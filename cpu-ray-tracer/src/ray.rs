@@ -1,6 +1,54 @@
+use common::scene::CullMode;
+
+// Offsets for moving ray origins off of a surface, from "A Fast and Robust Method for Avoiding
+// Self-Intersection" (Wächter and Binder, Ray Tracing Gems, chapter 6)
+const ORIGIN: f32 = 1.0 / 32.0;
+const FLOAT_SCALE: f32 = 1.0 / 65536.0;
+const INT_SCALE: f32 = 256.0;
+
+#[derive(Debug, Clone, Copy)]
 pub struct Ray {
     origin: glam::Vec3,
     direction: glam::Vec3,
+    cull_mode: CullMode,
+    // Only hits with t_min < t < t_max count
+    t_min: f32,
+    t_max: f32,
+    shear: Shear,
+}
+
+// Per-ray constants for the watertight triangle test. The axes are permuted so that z is the
+// direction's largest dimension, then sheared so that the direction becomes +z.
+#[derive(Debug, Clone, Copy)]
+pub struct Shear {
+    pub kx: usize,
+    pub ky: usize,
+    pub kz: usize,
+    pub sx: f32,
+    pub sy: f32,
+    pub sz: f32,
+}
+
+impl Shear {
+    fn new(direction: glam::Vec3) -> Self {
+        let kz = direction.abs().max_position();
+        let kx = (kz + 1) % 3;
+        let ky = (kx + 1) % 3;
+        // Swapping x and y keeps the winding of the triangles the same when z gets flipped
+        let (kx, ky) = if direction[kz] < 0.0 {
+            (ky, kx)
+        } else {
+            (kx, ky)
+        };
+        Self {
+            kx,
+            ky,
+            kz,
+            sx: direction[kx] / direction[kz],
+            sy: direction[ky] / direction[kz],
+            sz: 1.0 / direction[kz],
+        }
+    }
 }
 
 impl Ray {
@@ -14,22 +62,132 @@ impl Ray {
         &self.direction
     }
 
+    #[inline]
+    pub fn cull_mode(&self) -> CullMode {
+        self.cull_mode
+    }
+
+    #[inline]
+    pub fn shear(&self) -> &Shear {
+        &self.shear
+    }
+
+    #[inline]
+    pub fn t_min(&self) -> f32 {
+        self.t_min
+    }
+
+    #[inline]
+    pub fn t_max(&self) -> f32 {
+        self.t_max
+    }
+
     pub fn new(origin: glam::Vec3, direction: glam::Vec3) -> Self {
+        let direction = direction.normalize();
         Ray {
             origin,
-            direction: direction.normalize(),
+            direction,
+            cull_mode: CullMode::None,
+            t_min: 0.0,
+            t_max: f32::INFINITY,
+            shear: Shear::new(direction),
         }
     }
 
+    // A ray leaving a surface at point. The origin is moved off the surface along the geometric
+    // normal, to the side that the ray leaves on, far enough that the ray can't hit the surface it
+    // started on again. How far depends on the magnitude of point, since that's what determines
+    // the floating point error in it.
+    pub fn from_surface(
+        point: glam::Vec3,
+        geometric_normal: glam::Vec3,
+        direction: glam::Vec3,
+    ) -> Self {
+        let normal = if direction.dot(geometric_normal) < 0.0 {
+            -geometric_normal
+        } else {
+            geometric_normal
+        };
+        Ray::new(offset_origin(point, normal), direction)
+    }
+
+    pub fn with_cull_mode(mut self, cull_mode: CullMode) -> Self {
+        self.cull_mode = cull_mode;
+        self
+    }
+
+    pub fn with_interval(mut self, t_min: f32, t_max: f32) -> Self {
+        self.t_min = t_min;
+        self.t_max = t_max;
+        self
+    }
+
     pub fn from_camera(camera: &common::camera::Camera, ndc: glam::Vec2) -> Self {
         let origin = camera.origin();
         let direction = camera.ndc_to_viewing_direction(ndc);
 
         // return Ray::new(origin, direction);
-        Ray { origin, direction }
+        Ray {
+            origin,
+            direction,
+            cull_mode: CullMode::None,
+            t_min: 0.0,
+            t_max: f32::INFINITY,
+            shear: Shear::new(direction),
+        }
     }
 
-    pub fn at_t(&self, t: f32) -> glam::Vec3 {
-        self.origin + t * self.direction
+    // The same ray in another space. The direction isn't normalized again, so that distances along
+    // the ray stay the same in both spaces.
+    pub fn transformed(&self, transform: &glam::Affine3A) -> Self {
+        let direction = transform.transform_vector3(self.direction);
+        Ray {
+            origin: transform.transform_point3(self.origin),
+            direction,
+            shear: Shear::new(direction),
+            ..*self
+        }
+    }
+}
+
+// Moves point a few ULPs along normal. Near the origin, where ULPs get tiny, it falls back to a
+// small fixed offset instead.
+fn offset_origin(point: glam::Vec3, normal: glam::Vec3) -> glam::Vec3 {
+    let offset = |p: f32, n: f32| {
+        if p.abs() < ORIGIN {
+            p + FLOAT_SCALE * n
+        } else {
+            // Stepping the bits moves p away from zero when the offset is positive, so flip it
+            // for negative p
+            let ulps = (INT_SCALE * n) as i32;
+            let ulps = if p < 0.0 { -ulps } else { ulps };
+            f32::from_bits((p.to_bits() as i32).wrapping_add(ulps) as u32)
+        }
+    };
+    glam::Vec3::new(
+        offset(point.x, normal.x),
+        offset(point.y, normal.y),
+        offset(point.z, normal.z),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_offset_origin_moves_along_normal() {
+        let normal = glam::Vec3::new(1.0, -1.0, 0.0).normalize();
+        for point in [
+            glam::Vec3::ZERO,
+            glam::Vec3::splat(0.01),
+            glam::Vec3::new(1.0, -2.0, 3.0),
+            glam::Vec3::new(-1000.0, 1000.0, 1e6),
+        ] {
+            let offset = offset_origin(point, normal) - point;
+            assert!(offset.x > 0.0, "{point}: {offset}");
+            assert!(offset.y < 0.0, "{point}: {offset}");
+            assert_eq!(offset.z, 0.0, "{point}: {offset}");
+        }
     }
 }
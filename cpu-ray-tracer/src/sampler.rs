@@ -0,0 +1,419 @@
+use glam::{UVec2, Vec2};
+
+use crate::sampling::Rng;
+
+// Source of the sample values that the ray tracer turns into pixel positions, directions and
+// random decisions.
+// Values only depend on the pixel, the sample index and how many values were drawn before them
+// for that sample (the dimension). That makes renders reproducible across runs and thread counts.
+pub trait Sampler {
+    // Moves to sample `index` of a pixel, the next value drawn is its first dimension
+    fn start_sample(&mut self, pixel: UVec2, index: u32);
+
+    // Uniformly distributed in [0, 1)
+    fn next_1d(&mut self) -> f32;
+
+    // Uniformly distributed in [0, 1)^2
+    fn next_2d(&mut self) -> Vec2;
+}
+
+// Which sampler the ray tracer uses
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SamplerKind {
+    // Uncorrelated random numbers, the noisiest
+    Independent,
+    // Jittered strata, randomly shuffled per pixel and dimension
+    #[default]
+    Stratified,
+    // Halton sequence, rotated per pixel
+    Halton,
+    // Sobol sequence with hash based Owen scrambling per pixel and dimension
+    Sobol,
+}
+
+impl SamplerKind {
+    // Stratification is laid out for `samples_per_pixel`, samples beyond that wrap around
+    pub fn create(&self, samples_per_pixel: u32) -> Box<dyn Sampler> {
+        match self {
+            SamplerKind::Independent => Box::new(IndependentSampler::default()),
+            SamplerKind::Stratified => Box::new(StratifiedSampler::new(samples_per_pixel)),
+            SamplerKind::Halton => Box::new(HaltonSampler::default()),
+            SamplerKind::Sobol => Box::new(SobolSampler::default()),
+        }
+    }
+}
+
+// The state shared by all samplers
+#[derive(Debug, Clone, Copy, Default)]
+struct SampleState {
+    pixel_seed: u64,
+    index: u32,
+    dimension: u32,
+}
+
+impl SampleState {
+    fn start(&mut self, pixel: UVec2, index: u32) {
+        self.pixel_seed = hash(&[pixel.x as u64, pixel.y as u64]);
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    // Moves on by `count` dimensions, returns the first one
+    fn take_dimensions(&mut self, count: u32) -> u32 {
+        let dimension = self.dimension;
+        self.dimension += count;
+        dimension
+    }
+
+    // A hash that's different for every pixel and dimension, but the same for every sample
+    fn dimension_seed(&self, dimension: u32) -> u64 {
+        hash(&[self.pixel_seed, dimension as u64])
+    }
+
+    // A hash that's different for every pixel, dimension and sample
+    fn sample_seed(&self, dimension: u32) -> u64 {
+        hash(&[self.pixel_seed, dimension as u64, self.index as u64])
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct IndependentSampler {
+    state: SampleState,
+}
+
+impl Sampler for IndependentSampler {
+    fn start_sample(&mut self, pixel: UVec2, index: u32) {
+        self.state.start(pixel, index);
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        let dimension = self.state.take_dimensions(1);
+        to_unit_float(self.state.sample_seed(dimension) as u32)
+    }
+
+    fn next_2d(&mut self) -> Vec2 {
+        let dimension = self.state.take_dimensions(2);
+        let mut rng = Rng::new(self.state.sample_seed(dimension), 0);
+        rng.next_vec2()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StratifiedSampler {
+    state: SampleState,
+    samples_per_pixel: u32,
+}
+
+impl StratifiedSampler {
+    pub fn new(samples_per_pixel: u32) -> Self {
+        Self {
+            state: SampleState::default(),
+            samples_per_pixel: samples_per_pixel.max(1),
+        }
+    }
+
+    // Which stratum out of `strata` the current sample falls in. Shuffled per pixel and
+    // dimension, so that the dimensions aren't correlated with each other.
+    fn stratum(&self, dimension: u32, strata: u32) -> u32 {
+        let index = self.state.index % self.samples_per_pixel;
+        permute(index, strata, self.state.dimension_seed(dimension) as u32)
+    }
+
+    fn jitter(&self, dimension: u32) -> Vec2 {
+        Rng::new(self.state.sample_seed(dimension), 0).next_vec2()
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_sample(&mut self, pixel: UVec2, index: u32) {
+        self.state.start(pixel, index);
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        let dimension = self.state.take_dimensions(1);
+        let stratum = self.stratum(dimension, self.samples_per_pixel);
+        (stratum as f32 + self.jitter(dimension).x) / self.samples_per_pixel as f32
+    }
+
+    fn next_2d(&mut self) -> Vec2 {
+        // A grid with at least one cell per sample, as square as possible. When samples_per_pixel
+        // doesn't fill it (like 10 samples on 4x3 cells), every sample still gets a cell of its
+        // own, and the cells that are left empty are picked at random per pixel and dimension.
+        let dimension = self.state.take_dimensions(2);
+        let columns = (self.samples_per_pixel as f32).sqrt().ceil() as u32;
+        let rows = self.samples_per_pixel.div_ceil(columns);
+
+        let cell = self.stratum(dimension, columns * rows);
+        let cell = Vec2::new((cell % columns) as f32, (cell / columns) as f32);
+        (cell + self.jitter(dimension)) / Vec2::new(columns as f32, rows as f32)
+    }
+}
+
+// One prime base per dimension, dimensions beyond these get random values
+const HALTON_PRIMES: [u32; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131,
+];
+
+#[derive(Debug, Clone, Default)]
+pub struct HaltonSampler {
+    state: SampleState,
+}
+
+impl HaltonSampler {
+    fn sample(&self, dimension: u32) -> f32 {
+        let Some(&base) = HALTON_PRIMES.get(dimension as usize) else {
+            return to_unit_float(self.state.sample_seed(dimension) as u32);
+        };
+        // Every pixel gets the same sequence, shifted by a random offset (Cranley-Patterson rotation)
+        let offset = to_unit_float(self.state.dimension_seed(dimension) as u32);
+        let value = radical_inverse(base, self.state.index) + offset;
+        (value - value.floor()).min(ONE_MINUS_EPSILON)
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_sample(&mut self, pixel: UVec2, index: u32) {
+        self.state.start(pixel, index);
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        let dimension = self.state.take_dimensions(1);
+        self.sample(dimension)
+    }
+
+    fn next_2d(&mut self) -> Vec2 {
+        let dimension = self.state.take_dimensions(2);
+        Vec2::new(self.sample(dimension), self.sample(dimension + 1))
+    }
+}
+
+// Sobol direction numbers of the first two dimensions: the first is the van der Corput sequence,
+// the second comes from the primitive polynomial x + 1
+const SOBOL_DIRECTIONS: [[u32; 32]; 2] = {
+    let mut directions = [[0; 32]; 2];
+    let mut i = 0;
+    while i < 32 {
+        directions[0][i] = 1 << (31 - i);
+        directions[1][i] = if i == 0 {
+            1 << 31
+        } else {
+            directions[1][i - 1] ^ (directions[1][i - 1] >> 1)
+        };
+        i += 1;
+    }
+    directions
+};
+
+// Owen-scrambled Sobol, following "Practical Hash-based Owen Scrambling" (Burley 2020).
+// Every 1D or 2D draw uses the first one or two Sobol dimensions, with the sample index shuffled
+// per dimension so that draws don't correlate with each other. Power of two sample counts are
+// perfectly stratified.
+#[derive(Debug, Clone, Default)]
+pub struct SobolSampler {
+    state: SampleState,
+}
+
+impl SobolSampler {
+    fn sample(&mut self, dimensions: u32) -> [f32; 2] {
+        let dimension = self.state.take_dimensions(dimensions);
+        let seed = self.state.dimension_seed(dimension);
+        let index = nested_uniform_scramble(self.state.index, seed as u32);
+        [0, 1].map(|d| {
+            let value = sobol(index, d);
+            let seed = hash(&[seed, d as u64]) as u32;
+            to_unit_float(nested_uniform_scramble(value, seed))
+        })
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_sample(&mut self, pixel: UVec2, index: u32) {
+        self.state.start(pixel, index);
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        self.sample(1)[0]
+    }
+
+    fn next_2d(&mut self) -> Vec2 {
+        Vec2::from_array(self.sample(2))
+    }
+}
+
+fn sobol(mut index: u32, dimension: usize) -> u32 {
+    let mut value = 0;
+    let mut bit = 0;
+    while index != 0 {
+        if index & 1 != 0 {
+            value ^= SOBOL_DIRECTIONS[dimension][bit];
+        }
+        index >>= 1;
+        bit += 1;
+    }
+    value
+}
+
+// Owen scrambling of the bits of x, from the most significant one down
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+// Only ever changes bits based on less significant ones, which is Owen scrambling in reverse
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x
+}
+
+fn radical_inverse(base: u32, mut index: u32) -> f32 {
+    let inverse_base = 1.0 / base as f64;
+    let mut value = 0.0;
+    let mut factor = inverse_base;
+    while index > 0 {
+        value += (index % base) as f64 * factor;
+        index /= base;
+        factor *= inverse_base;
+    }
+    value as f32
+}
+
+// Random permutation of [0, length) chosen by the seed, from "Correlated Multi-Jittered
+// Sampling" (Kensler 2013)
+fn permute(mut i: u32, length: u32, seed: u32) -> u32 {
+    let mut mask = length.wrapping_sub(1);
+    mask |= mask >> 1;
+    mask |= mask >> 2;
+    mask |= mask >> 4;
+    mask |= mask >> 8;
+    mask |= mask >> 16;
+
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= seed >> 16;
+        i ^= (i & mask) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= seed >> 23;
+        i ^= (i & mask) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & mask) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & mask) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & mask) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= mask;
+        i ^= i >> 5;
+        if i < length {
+            break;
+        }
+    }
+    (i.wrapping_add(seed)) % length
+}
+
+const ONE_MINUS_EPSILON: f32 = 1.0 - f32::EPSILON / 2.0;
+
+// The top 24 bits, which are exactly representable as an f32
+fn to_unit_float(x: u32) -> f32 {
+    (x >> 8) as f32 / (1 << 24) as f32
+}
+
+// SplitMix64 finalizer
+fn mix(mut x: u64) -> u64 {
+    x ^= x >> 30;
+    x = x.wrapping_mul(0xbf58476d1ce4e5b9);
+    x ^= x >> 27;
+    x = x.wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+fn hash(values: &[u64]) -> u64 {
+    values
+        .iter()
+        .fold(0x9e3779b97f4a7c15, |hash, &value| mix(hash ^ mix(value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KINDS: [SamplerKind; 4] = [
+        SamplerKind::Independent,
+        SamplerKind::Stratified,
+        SamplerKind::Halton,
+        SamplerKind::Sobol,
+    ];
+
+    fn draw(sampler: &mut dyn Sampler, pixel: UVec2, index: u32) -> [f32; 5] {
+        sampler.start_sample(pixel, index);
+        let a = sampler.next_2d();
+        let b = sampler.next_1d();
+        let c = sampler.next_2d();
+        [a.x, a.y, b, c.x, c.y]
+    }
+
+    #[test]
+    fn test_samples_in_range_and_deterministic() {
+        for kind in KINDS {
+            let mut first = kind.create(16);
+            let mut second = kind.create(16);
+            for index in 0..64 {
+                for pixel in [UVec2::new(0, 0), UVec2::new(17, 3)] {
+                    let values = draw(first.as_mut(), pixel, index);
+                    assert!(
+                        values.iter().all(|v| (0.0..1.0).contains(v)),
+                        "{kind:?}: {values:?}"
+                    );
+                    // Drawing in a different order gives the same values
+                    draw(second.as_mut(), UVec2::new(5, 5), index);
+                    assert_eq!(values, draw(second.as_mut(), pixel, index), "{kind:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_stratified_2d() {
+        // 16 samples on a 4x4 grid should put exactly one sample in every cell
+        for kind in [SamplerKind::Stratified, SamplerKind::Sobol] {
+            let mut sampler = kind.create(16);
+            let mut cells = [0; 16];
+            for index in 0..16 {
+                sampler.start_sample(UVec2::new(3, 7), index);
+                let cell = (sampler.next_2d() * 4.0).floor();
+                cells[cell.y as usize * 4 + cell.x as usize] += 1;
+            }
+            assert_eq!(cells, [1; 16], "{kind:?}");
+        }
+    }
+
+    #[test]
+    fn test_stratified_2d_uneven() {
+        // Sample counts that aren't square leave some cells of the grid empty, but never put two
+        // samples in the same cell
+        for samples_per_pixel in [2_u32, 3, 5, 10, 12] {
+            let columns = (samples_per_pixel as f32).sqrt().ceil() as u32;
+            let rows = samples_per_pixel.div_ceil(columns);
+            let grid = Vec2::new(columns as f32, rows as f32);
+
+            let mut sampler = SamplerKind::Stratified.create(samples_per_pixel);
+            for pixel in [UVec2::new(3, 7), UVec2::new(40, 2)] {
+                let mut cells = vec![0; (columns * rows) as usize];
+                for index in 0..samples_per_pixel {
+                    sampler.start_sample(pixel, index);
+                    let cell = (sampler.next_2d() * grid).floor();
+                    cells[(cell.y * grid.x + cell.x) as usize] += 1;
+                }
+                assert!(cells.iter().all(|&count| count <= 1), "{cells:?}");
+                let empty = cells.iter().filter(|&&count| count == 0).count() as u32;
+                assert_eq!(empty, columns * rows - samples_per_pixel, "{cells:?}");
+            }
+        }
+    }
+}
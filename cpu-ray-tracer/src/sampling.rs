@@ -0,0 +1,60 @@
+use core::f32;
+
+use glam::{Vec2, Vec3};
+
+// PCG32 random number generator, see https://www.pcg-random.org.
+// Small and fast, and seeding it per pixel keeps renders deterministic no matter which thread
+// traces which pixel.
+#[derive(Debug, Clone, Copy)]
+pub struct Rng {
+    state: u64,
+    increment: u64,
+}
+
+impl Rng {
+    const MULTIPLIER: u64 = 6364136223846793005;
+
+    pub fn new(seed: u64, stream: u64) -> Self {
+        let mut rng = Self {
+            state: 0,
+            increment: (stream << 1) | 1,
+        };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old_state = self.state;
+        self.state = old_state
+            .wrapping_mul(Self::MULTIPLIER)
+            .wrapping_add(self.increment);
+        let xor_shifted = (((old_state >> 18) ^ old_state) >> 27) as u32;
+        let rotation = (old_state >> 59) as u32;
+        xor_shifted.rotate_right(rotation)
+    }
+
+    // Uniformly distributed in [0, 1)
+    pub fn next_f32(&mut self) -> f32 {
+        // The top 24 bits are exactly representable as an f32
+        (self.next_u32() >> 8) as f32 / (1 << 24) as f32
+    }
+
+    pub fn next_vec2(&mut self) -> Vec2 {
+        Vec2::new(self.next_f32(), self.next_f32())
+    }
+}
+
+// Turns a uniform sample in [0, 1)^2 into a direction in the hemisphere around the normal, with a
+// probability density of cos(theta) / pi
+pub fn cosine_hemisphere(normal: Vec3, u: Vec2) -> Vec3 {
+    // Uniformly sample the unit disk and project it up onto the hemisphere (Malley's method)
+    let radius = u.x.sqrt();
+    let phi = 2.0 * f32::consts::PI * u.y;
+    let (sin_phi, cos_phi) = phi.sin_cos();
+    let z = (1.0 - u.x).max(0.0).sqrt();
+
+    let (tangent, bitangent) = normal.any_orthonormal_pair();
+    (tangent * (radius * cos_phi) + bitangent * (radius * sin_phi) + normal * z).normalize()
+}
@@ -0,0 +1,27 @@
+// The scenes in assets/scenes, shared by the tests
+
+use common::model::{
+    format::obj::load_obj,
+    triangle::{Mesh, Triangle},
+};
+
+// Every mesh in one of the scenes
+pub(crate) fn scene_meshes(name: &str) -> Vec<Mesh> {
+    load_obj(format!(
+        "{}/../assets/scenes/{name}/{name}.obj",
+        env!("CARGO_MANIFEST_DIR")
+    ))
+}
+
+// All the triangles of one of the scenes, wound counter-clockwise
+pub(crate) fn scene_triangles(name: &str) -> Vec<Triangle> {
+    scene_meshes(name)
+        .iter()
+        .flat_map(|mesh| mesh.counter_clockwise_triangles())
+        .collect()
+}
+
+// The cube scene's only mesh
+pub(crate) fn cube() -> Mesh {
+    scene_meshes("cube").remove(0)
+}